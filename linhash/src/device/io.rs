@@ -2,7 +2,7 @@ use super::*;
 
 use rustix::fd::OwnedFd;
 use rustix::fs::{FallocateFlags, fallocate};
-use rustix::fs::{Mode, OFlags, fdatasync, open};
+use rustix::fs::{Mode, OFlags, fdatasync, fstat, ftruncate, open};
 use rustix::io::{ReadWriteFlags, preadv2, pwritev2};
 
 pub struct IO {
@@ -24,6 +24,7 @@ impl IO {
     }
 
     pub fn write(&self, buf: &PageIOBuffer, offset: u64) -> Result<()> {
+        #[cfg_attr(not(feature = "atomic-write"), allow(unused_mut))]
         let mut flags = ReadWriteFlags::empty();
 
         #[cfg(feature = "atomic-write")]
@@ -43,6 +44,16 @@ impl IO {
         Ok(())
     }
 
+    pub fn file_size(&self) -> Result<u64> {
+        let stat = fstat(&self.fd)?;
+        Ok(stat.st_size as u64)
+    }
+
+    pub fn truncate(&self, len: u64) -> Result<()> {
+        ftruncate(&self.fd, len)?;
+        Ok(())
+    }
+
     pub fn free(&self, offset: u64, len: u64) -> Result<()> {
         fallocate(
             &self.fd,
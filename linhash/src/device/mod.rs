@@ -8,6 +8,7 @@ const HEADER_LEN: usize = 32;
 
 pub struct Device {
     io: IO,
+    path: PathBuf,
     pagesize: usize,
 }
 
@@ -15,12 +16,21 @@ impl Device {
     pub fn new(path: &Path, pagesize: usize) -> Result<Self> {
         Ok(Self {
             io: IO::new(path)?,
+            path: path.to_owned(),
             pagesize,
         })
     }
 
-    fn into_data(&self, page: &Page) -> PageIOBuffer {
-        let data = encode_page(&page);
+    pub fn corruption(&self, page_id: u64, reason: CorruptionReason) -> Error {
+        Error::Corruption {
+            file: self.path.clone(),
+            page_id,
+            reason,
+        }
+    }
+
+    fn encode(&self, page: &Page) -> PageIOBuffer {
+        let data = encode_page(page);
         assert!(data.len() <= self.pagesize - HEADER_LEN);
 
         let crc = crc32fast::hash(&data);
@@ -38,59 +48,90 @@ impl Device {
     }
 
     pub fn write_page(&self, id: u64, page: &Page) -> Result<()> {
-        let buf = self.into_data(page);
+        let buf = self.encode(page);
         self.io.write(&buf, id * self.pagesize as u64)?;
         Ok(())
     }
 
-    pub fn read_page(&self, id: u64) -> Result<Option<Page>> {
+    /// Read the page and verify its header and checksum.
+    /// Returns `None` if the page has never been written (or has been freed).
+    fn read_verified(&self, id: u64) -> Result<Option<(PageIOBuffer, Range<usize>)>> {
         let mut buf = PageIOBuffer::with_capacity(self.pagesize);
         buf.resize(self.pagesize, 0);
+
         self.io.read(&mut buf, id * self.pagesize as u64)?;
 
+        // Reading beyond the end of the file or a punched hole gives zeros.
+        if buf[0..HEADER_LEN].iter().all(|&x| x == 0) {
+            return Ok(None);
+        }
+
         let stored_magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
         if stored_magic != MAGIC {
-            return Ok(None);
+            return Err(self.corruption(id, CorruptionReason::BadMagic));
         }
 
         let stored_crc = u32::from_le_bytes(buf[4..8].try_into().unwrap());
         let data_len = u32::from_le_bytes(buf[8..12].try_into().unwrap()) as usize;
+        if data_len > self.pagesize - HEADER_LEN {
+            return Err(self.corruption(id, CorruptionReason::BadLength));
+        }
 
-        let data = &buf[HEADER_LEN..(HEADER_LEN + data_len)];
-        let calc_crc = crc32fast::hash(data);
+        let data_range = HEADER_LEN..(HEADER_LEN + data_len);
+        let calc_crc = crc32fast::hash(&buf[data_range.clone()]);
         if stored_crc != calc_crc {
-            return Ok(None);
+            return Err(self.corruption(id, CorruptionReason::ChecksumMismatch));
         }
 
-        match decode_page(data) {
+        Ok(Some((buf, data_range)))
+    }
+
+    /// Returns `None` if the page has never been written.
+    pub fn try_read_page(&self, id: u64) -> Result<Option<Page>> {
+        let Some((buf, data_range)) = self.read_verified(id)? else {
+            return Ok(None);
+        };
+
+        match decode_page(&buf[data_range]) {
             Ok(page) => Ok(Some(page)),
-            Err(_) => Ok(None),
+            Err(_) => Err(self.corruption(id, CorruptionReason::Decode)),
         }
     }
 
-    pub fn read_page_ref(&self, id: u64) -> Result<Option<PageRef>> {
-        let mut buf = PageIOBuffer::with_capacity(self.pagesize);
-        buf.resize(self.pagesize, 0);
-
-        self.io.read(&mut buf, id * self.pagesize as u64)?;
+    /// Read a page which must have been written.
+    pub fn read_page(&self, id: u64) -> Result<Page> {
+        match self.try_read_page(id)? {
+            Some(page) => Ok(page),
+            None => Err(self.corruption(id, CorruptionReason::NotWritten)),
+        }
+    }
 
-        let stored_magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
-        if stored_magic != MAGIC {
+    /// Returns `None` if the page has never been written.
+    pub fn try_read_page_ref(&self, id: u64) -> Result<Option<PageRef>> {
+        let Some((buf, data_range)) = self.read_verified(id)? else {
             return Ok(None);
-        }
+        };
 
-        let stored_crc = u32::from_le_bytes(buf[4..8].try_into().unwrap());
-        let data_len = u32::from_le_bytes(buf[8..12].try_into().unwrap()) as usize;
+        // The checksum is verified so the archive is assumed to be valid.
+        Ok(Some(PageRef { buf, data_range }))
+    }
 
-        let data_range = HEADER_LEN..(HEADER_LEN + data_len);
-        let calc_crc = crc32fast::hash(&buf[data_range.clone()]);
-        if stored_crc != calc_crc {
-            return Ok(None);
+    /// Read a page which must have been written.
+    pub fn read_page_ref(&self, id: u64) -> Result<PageRef> {
+        match self.try_read_page_ref(id)? {
+            Some(page) => Ok(page),
+            None => Err(self.corruption(id, CorruptionReason::NotWritten)),
         }
+    }
 
-        let page_ref = PageRef { buf, data_range };
-
-        Ok(Some(page_ref))
+    /// True if the page has been written and not freed.
+    /// A corrupted page counts as written.
+    pub fn is_written(&self, id: u64) -> Result<bool> {
+        match self.read_verified(id) {
+            Ok(page) => Ok(page.is_some()),
+            Err(Error::Corruption { .. }) => Ok(true),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn flush(&self) -> Result<()> {
@@ -98,7 +139,19 @@ impl Device {
         Ok(())
     }
 
+    /// The number of pages the file can hold.
+    /// Pages beyond this have never been written.
+    pub fn n_pages(&self) -> Result<u64> {
+        let size = self.io.file_size()?;
+        Ok(size.div_ceil(self.pagesize as u64))
+    }
+
     /// Free the storage blocks of pages in [start, end).
+    /// Drop the pages at and beyond `n_pages`.
+    pub fn truncate(&self, n_pages: u64) -> Result<()> {
+        self.io.truncate(n_pages * self.pagesize as u64)
+    }
+
     pub fn free_page_range(&self, start: u64, end: u64) -> Result<()> {
         let n_pages = end - start;
         self.io
@@ -108,6 +161,7 @@ impl Device {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod tests {
     use super::*;
 
@@ -126,7 +180,7 @@ mod tests {
 
         device.write_page(3, &page).unwrap();
 
-        let read_page = device.read_page(3).unwrap().unwrap();
+        let read_page = device.read_page(3).unwrap();
         assert_eq!(read_page.kv_pairs.get(&vec![1; 32]), Some(&vec![1; 16]));
         assert_eq!(read_page.kv_pairs.get(&vec![2; 32]), Some(&vec![2; 16]));
     }
@@ -146,8 +200,61 @@ mod tests {
 
         device.write_page(3, &page).unwrap();
 
-        let page_ref = device.read_page_ref(3).unwrap().unwrap();
+        let page_ref = device.read_page_ref(3).unwrap();
         assert_eq!(page_ref.get_value(&vec![1; 32]), Some(&vec![1; 16][..]));
         assert_eq!(page_ref.get_value(&vec![2; 32]), Some(&vec![2; 16][..]));
     }
+
+    #[test]
+    fn test_read_unwritten_page() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let device = Device::new(f.path(), 8192).unwrap();
+
+        device.write_page(3, &Page::new()).unwrap();
+
+        assert!(device.try_read_page(2).unwrap().is_none());
+        assert!(device.try_read_page_ref(4).unwrap().is_none());
+        assert!(matches!(
+            device.read_page(2),
+            Err(Error::Corruption {
+                page_id: 2,
+                reason: CorruptionReason::NotWritten,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_read_corrupted_page() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let device = Device::new(f.path(), 8192).unwrap();
+
+        let mut page = Page::new();
+        page.insert(vec![1; 32], vec![1; 16]);
+        device.write_page(3, &page).unwrap();
+
+        // Flip a bit in the data area.
+        let mut buf = PageIOBuffer::with_capacity(8192);
+        buf.resize(8192, 0);
+        device.io.read(&mut buf, 3 * 8192).unwrap();
+        buf[HEADER_LEN + 1] ^= 1;
+        device.io.write(&buf, 3 * 8192).unwrap();
+
+        assert!(matches!(
+            device.read_page(3),
+            Err(Error::Corruption {
+                page_id: 3,
+                reason: CorruptionReason::ChecksumMismatch,
+                ..
+            })
+        ));
+        assert!(matches!(
+            device.try_read_page_ref(3),
+            Err(Error::Corruption {
+                page_id: 3,
+                reason: CorruptionReason::ChecksumMismatch,
+                ..
+            })
+        ));
+    }
 }
@@ -1,7 +1,48 @@
+use std::path::PathBuf;
+
+use crate::HashMode;
+
+/// Why a page is considered corrupted.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+pub enum CorruptionReason {
+    #[error("page is expected but never written")]
+    NotWritten,
+    #[error("bad magic number")]
+    BadMagic,
+    #[error("bad data length")]
+    BadLength,
+    #[error("checksum mismatch")]
+    ChecksumMismatch,
+    #[error("failed to decode")]
+    Decode,
+    #[error("locallevel doesn't match the table layout")]
+    LocalLevel,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Local level mismatch")]
     LocalLevelMismatch,
+    #[error("Version mismatch")]
+    VersionMismatch,
+    #[error("Config mismatch: {field} is {found} on disk but {expected} is given")]
+    ConfigMismatch {
+        field: &'static str,
+        expected: u64,
+        found: u64,
+    },
+    #[error("Hash mode mismatch: {found:?} on disk but this build uses {expected:?}")]
+    HashModeMismatch { expected: HashMode, found: HashMode },
+    #[error("Unsupported format version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Corrupted page {page_id} in {}: {reason}", file.display())]
+    Corruption {
+        file: PathBuf,
+        page_id: u64,
+        reason: CorruptionReason,
+    },
+    #[error("Invalid metadata file {0}")]
+    InvalidMeta(PathBuf),
     #[error(transparent)]
     Rkyv(#[from] rkyv::rancor::Error),
     #[error(transparent)]
@@ -0,0 +1,118 @@
+use super::*;
+
+/// Overflow pages which are not referenced by any page chain.
+///
+/// A page freed by an operation can't be reused right away:
+/// operations in flight may still be reading it and
+/// the primary page which referenced it may not be persisted yet.
+/// So it is kept pending until GC reclaims it at a quiescent point.
+#[derive(Default)]
+pub struct FreeList {
+    pending: Vec<u64>,
+    // Taken by GC which is reclaiming them.
+    reclaiming: BTreeSet<u64>,
+    // The smallest id is reused first to keep the file compact.
+    free: BTreeSet<u64>,
+}
+
+impl FreeList {
+    pub fn new(free: impl IntoIterator<Item = u64>) -> Self {
+        Self {
+            pending: Vec::new(),
+            reclaiming: BTreeSet::new(),
+            free: free.into_iter().collect(),
+        }
+    }
+
+    pub fn alloc(&mut self) -> Option<u64> {
+        self.free.pop_first()
+    }
+
+    pub fn release(&mut self, ids: impl IntoIterator<Item = u64>) {
+        self.pending.extend(ids);
+    }
+
+    pub fn n_pending(&self) -> u64 {
+        self.pending.len() as u64
+    }
+
+    /// Take the pending pages as sorted ranges of contiguous ids.
+    pub fn take_pending(&mut self) -> Vec<Range<u64>> {
+        let mut ids = std::mem::take(&mut self.pending);
+        ids.sort_unstable();
+        ids.dedup();
+        self.reclaiming.extend(&ids);
+
+        let mut out: Vec<Range<u64>> = Vec::new();
+        for id in ids {
+            match out.last_mut() {
+                Some(range) if range.end == id => range.end += 1,
+                _ => out.push(id..id + 1),
+            }
+        }
+        out
+    }
+
+    /// Make the taken pages reusable.
+    pub fn reclaim(&mut self, ranges: Vec<Range<u64>>) {
+        for id in ranges.into_iter().flatten() {
+            self.reclaiming.remove(&id);
+            self.free.insert(id);
+        }
+    }
+
+    /// Put the taken pages back to pending when GC failed to reclaim them.
+    pub fn abort_reclaim(&mut self, ranges: Vec<Range<u64>>) {
+        for id in ranges.into_iter().flatten() {
+            self.reclaiming.remove(&id);
+            self.pending.push(id);
+        }
+    }
+
+    pub fn n_free(&self) -> u64 {
+        self.free.len() as u64
+    }
+
+    /// All the pages not referenced including the ones not reclaimed yet.
+    /// Nobody reads them after a restart
+    /// so they are reusable then once the pages which referenced them are persisted.
+    pub fn reusable_ids(&self) -> Vec<u64> {
+        let mut ids = self.free.clone();
+        ids.extend(&self.reclaiming);
+        ids.extend(&self.pending);
+        ids.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_list() {
+        let mut list = FreeList::new([5]);
+        list.release([3, 1, 2, 7, 2]);
+        assert_eq!(list.n_pending(), 5);
+
+        // Pending pages are not reused until reclaimed.
+        assert_eq!(list.alloc(), Some(5));
+        assert_eq!(list.alloc(), None);
+
+        let ranges = list.take_pending();
+        assert_eq!(ranges, vec![1..4, 7..8]);
+        assert_eq!(list.n_pending(), 0);
+
+        list.reclaim(ranges);
+        assert_eq!(list.n_free(), 4);
+        assert_eq!(list.alloc(), Some(1));
+        assert_eq!(list.reusable_ids(), vec![2, 3, 7]);
+
+        list.release([9, 3]);
+        let ranges = list.take_pending();
+        list.release([10]);
+        assert_eq!(list.reusable_ids(), vec![2, 3, 7, 9, 10]);
+        list.abort_reclaim(ranges);
+        assert_eq!(list.n_pending(), 3);
+        assert_eq!(list.reusable_ids(), vec![2, 3, 7, 9, 10]);
+    }
+}
@@ -1,19 +1,26 @@
-use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
-use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::ops::Range;
-use std::path::Path;
+use parking_lot::{
+    Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockUpgradableReadGuard, RwLockWriteGuard,
+};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::ops::{ControlFlow, Range};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
 use std::time::Duration;
 
 mod error;
+mod free_list;
 mod lock;
-pub use error::Error;
 use error::Result;
+pub use error::{CorruptionReason, Error};
+pub use lock::LockStat;
 
 mod device;
 use device::Device;
+mod meta;
+pub use meta::HashMode;
 mod op;
+pub use op::{Partition, ScanBatch, ScanCursor};
 mod util;
 
 mod page;
@@ -21,6 +28,12 @@ use page::*;
 
 type PageIOBuffer = rkyv::util::AlignedVec<4096>;
 
+// GC is woken up when this many overflow pages are waiting to be reclaimed.
+const GC_BATCH: u64 = 64;
+
+/// The most partitions `LinHash::partitions` splits the table into.
+pub const MAX_PARTITIONS: u64 = 1 << 16;
+
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
 struct Page {
     kv_pairs: HashMap<Vec<u8>, Vec<u8>>,
@@ -58,9 +71,8 @@ fn calc_max_kv_per_page(ksize: usize, vsize: usize) -> u16 {
     for i in 0..u16::MAX {
         let mut k = vec![0; ksize];
         let ibytes: [u8; 2] = i.to_le_bytes();
-        for j in 0..std::cmp::min(2, ksize) {
-            k[j] = ibytes[j];
-        }
+        let m = std::cmp::min(2, ksize);
+        k[..m].copy_from_slice(&ibytes[..m]);
 
         let old = page.insert(k, vec![1; vsize]);
         if old.is_none() {
@@ -83,7 +95,7 @@ enum PageId {
     Overflow(u64),
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 struct PageChainId {
     primary_page_id: u64,
     locallevel: u8,
@@ -116,7 +128,7 @@ impl Root {
         }
     }
 
-    // Only this function updates `next_split_primary_page_id` and `base_level`.
+    // Only these functions update `next_split_primary_page_id` and `base_level`.
     fn advance_split_pointer(&mut self) {
         self.next_split_primary_page_id += 1;
         if self.next_split_primary_page_id == (1 << self.base_level) {
@@ -124,6 +136,112 @@ impl Root {
             self.next_split_primary_page_id = 0;
         }
     }
+
+    fn retreat_split_pointer(&mut self) {
+        if self.next_split_primary_page_id == 0 {
+            self.base_level -= 1;
+            self.next_split_primary_page_id = 1 << self.base_level;
+        }
+        self.next_split_primary_page_id -= 1;
+    }
+}
+
+/// The root published to the optimistic readers.
+/// Like a seqlock, the sequence number is odd while the root or the page layout is being changed
+/// so that the readers can detect the change without writing shared memory.
+/// The changes which readers can't see consistently are done between `begin_write`
+/// and the drop of the returned guard.
+struct RootSeq {
+    seq: AtomicU64,
+    base_level: AtomicU8,
+    next_split_primary_page_id: AtomicU64,
+}
+
+impl RootSeq {
+    fn new(root: &Root) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            base_level: AtomicU8::new(root.base_level),
+            next_split_primary_page_id: AtomicU64::new(root.next_split_primary_page_id),
+        }
+    }
+
+    /// Returns `None` while a writer is changing the root.
+    fn read(&self) -> Option<RootSeqReadGuard<'_>> {
+        let start_seq = self.seq.load(Ordering::SeqCst);
+        if !start_seq.is_multiple_of(2) {
+            return None;
+        }
+        let root = Root {
+            base_level: self.base_level.load(Ordering::SeqCst),
+            next_split_primary_page_id: self.next_split_primary_page_id.load(Ordering::SeqCst),
+        };
+        Some(RootSeqReadGuard {
+            seq: &self.seq,
+            start_seq,
+            root,
+        })
+    }
+
+    // The root write lock ensures a single writer.
+    fn begin_write(&self, _root: &RwLockWriteGuard<'_, Root>) -> RootSeqWriteGuard<'_> {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        RootSeqWriteGuard(self)
+    }
+
+    fn publish(&self, root: &Root) {
+        self.base_level.store(root.base_level, Ordering::SeqCst);
+        self.next_split_primary_page_id
+            .store(root.next_split_primary_page_id, Ordering::SeqCst);
+    }
+}
+
+struct RootSeqReadGuard<'a> {
+    seq: &'a AtomicU64,
+    start_seq: u64,
+    root: Root,
+}
+
+impl RootSeqReadGuard<'_> {
+    /// True if no writer overlapped since the root was read.
+    fn validate(&self) -> bool {
+        self.seq.load(Ordering::SeqCst) == self.start_seq
+    }
+}
+
+struct RootSeqWriteGuard<'a>(&'a RootSeq);
+
+impl RootSeqWriteGuard<'_> {
+    fn publish(&self, root: &Root) {
+        self.0.publish(root);
+    }
+}
+
+impl Drop for RootSeqWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.0.seq.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Sent to the split thread after a modification.
+#[derive(Clone, Copy)]
+enum ResizeEvent {
+    /// `chain_len` is the number of pages in the chain as far as the insert traversed.
+    Insert {
+        chain_len: usize,
+        overflow_allocated: bool,
+    },
+    Delete,
+}
+
+/// The resize events not handled by the split thread yet.
+/// The events are accumulated here and the wakeups are coalesced
+/// so the queue doesn't grow however far the split thread falls behind.
+#[derive(Default)]
+struct ResizeEvents {
+    /// The number of events which call for a split under the split policy.
+    n_split: u64,
+    deleted: bool,
 }
 
 enum OpEvent {
@@ -145,6 +263,17 @@ pub struct Statistics {
     n_insert_hit: u64,
     n_delete_miss: u64,
     n_delete_hit: u64,
+    /// The load factor when the statistics were taken.
+    pub load_factor: f64,
+    /// The number of splits the split thread is behind.
+    pub n_pending_splits: u64,
+    /// The number of splits done by inserts because the load factor exceeded `max_load_factor`.
+    pub n_assisted_splits: u64,
+    /// The number of split batches which failed. The failed bucket is retried on the next split.
+    pub n_failed_splits: u64,
+    /// The number of freed overflow pages GC has made reusable.
+    pub n_reclaimed_overflow_pages: u64,
+    pub lock_stat: LockStat,
 }
 
 impl Statistics {
@@ -173,6 +302,17 @@ impl Statistics {
         }
     }
 
+    fn add(&mut self, other: &Statistics) {
+        self.n_get_miss += other.n_get_miss;
+        self.n_get_miss_hops += other.n_get_miss_hops;
+        self.n_get_hit += other.n_get_hit;
+        self.n_get_hit_hops += other.n_get_hit_hops;
+        self.n_insert_miss += other.n_insert_miss;
+        self.n_insert_hit += other.n_insert_hit;
+        self.n_delete_miss += other.n_delete_miss;
+        self.n_delete_hit += other.n_delete_hit;
+    }
+
     pub fn show(&self) {
         println!(
             "GET Miss: {} times, avg hops: {}",
@@ -196,59 +336,442 @@ impl Statistics {
         println!("INSERT Hit: {} times", self.n_insert_hit);
         println!("DELETE Miss: {} times", self.n_delete_miss);
         println!("DELETE Hit: {} times", self.n_delete_hit);
+        println!("Load Factor: {}", self.load_factor);
+        println!("Pending Splits: {}", self.n_pending_splits);
+        println!("Assisted Splits: {}", self.n_assisted_splits);
+        println!("Failed Splits: {}", self.n_failed_splits);
+        println!(
+            "Reclaimed Overflow Pages: {}",
+            self.n_reclaimed_overflow_pages
+        );
+        println!(
+            "Lock Stripes: {}, acquired: {} times, contended: {} times, collisions: {} times",
+            self.lock_stat.n_stripes,
+            self.lock_stat.n_acquired,
+            self.lock_stat.n_contended,
+            self.lock_stat.n_collisions
+        );
     }
 }
 
+// Each thread pushes the events to the shard it was assigned on its first push
+// so that the operations, including the optimistic GETs, rarely write memory shared with other threads.
+// The shards are fixed so that threads come and go without growing them.
+// The shards are locked by the others only to sum them.
+struct ShardedStatistics {
+    shards: Box<[StatShard]>,
+}
+
+type StatShard = crossbeam::utils::CachePadded<Mutex<Statistics>>;
+
+const N_STAT_SHARDS: usize = 64;
+
+impl ShardedStatistics {
+    fn new() -> Self {
+        Self {
+            shards: (0..N_STAT_SHARDS).map(|_| StatShard::default()).collect(),
+        }
+    }
+
+    fn push(&self, evt: OpEvent) {
+        static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+        thread_local! {
+            // The threads are assigned the shards in turn so that the first ones don't share.
+            static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % N_STAT_SHARDS;
+        }
+        let i = SHARD.with(|x| *x);
+        self.shards[i].lock().push(evt);
+    }
+
+    fn sum(&self) -> Statistics {
+        let mut sum = Statistics::default();
+        for shard in self.shards.iter() {
+            sum.add(&shard.lock());
+        }
+        sum
+    }
+}
+
+/// What recovery found when the database was opened.
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    /// True if the database was created by this open.
+    pub created: bool,
+    /// True if the table was scanned because there was no clean checkpoint.
+    /// The overflow page statistics are only available after a full scan.
+    pub full_scan: bool,
+    /// True if the database was closed cleanly last time.
+    pub clean_shutdown: bool,
+    /// The number of items recorded at the last shutdown if it was clean.
+    pub n_items_at_shutdown: Option<u64>,
+    /// The number of items found by recovery.
+    pub n_items: u64,
+    /// The number of primary pages.
+    pub n_buckets: u64,
+    pub base_level: u8,
+    pub next_split_primary_page_id: u64,
+    /// The new primary page of a split which was rolled back because it wasn't committed.
+    pub discarded_split_page: Option<u64>,
+    /// The range of overflow pages referenced by the page chains.
+    pub overflow_range: Range<u64>,
+    /// The number of overflow pages referenced by the page chains.
+    pub n_overflow_pages: u64,
+    /// Overflow pages written but not referenced by any page chain.
+    /// They are left by splits or inserts interrupted by a crash.
+    /// GC frees them unless some buckets are damaged.
+    pub n_orphaned_overflow_pages: u64,
+    /// Overflow pages which can be reused.
+    pub n_free_overflow_pages: u64,
+    /// Buckets which can't be read. See `LinHash::damaged_buckets`.
+    pub damaged_buckets: Vec<u64>,
+    /// The time taken by recovery.
+    pub elapsed: Duration,
+}
+
 struct LinHashCore {
+    dir: PathBuf,
+
     primary_pages: Device,
 
     root: RwLock<Root>,
+    // GET reads this instead of locking the root.
+    root_seq: RootSeq,
     locks: lock::StripeLock,
+    // The stripes grow with the number of primary pages.
+    scale_lock_stripes: bool,
 
     overflow_pages: Device,
     next_overflow_id: AtomicU64,
+    free_list: Mutex<free_list::FreeList>,
+    n_reclaimed_overflow_pages: AtomicU64,
+    gc_tx: crossbeam::channel::Sender<()>,
+    gc_rx: crossbeam::channel::Receiver<()>,
 
     n_items: AtomicU64,
     max_kv_per_page: u16,
+    // Merges never shrink the table below this.
+    min_buckets: u64,
+
+    split_policy: SplitPolicy,
+    split_threads: u64,
+    // Inserts split by themselves when the load factor exceeds this.
+    max_load_factor: Option<f64>,
+    resize_events: Mutex<ResizeEvents>,
+    n_assisted_splits: AtomicU64,
+    n_failed_splits: AtomicU64,
+
+    // Buckets found unreadable on recovery.
+    damaged: Mutex<BTreeSet<u64>>,
+
+    // True if the checkpoint on disk may be stale.
+    dirty: AtomicBool,
+    checkpoint_lock: Mutex<()>,
 
-    stat: Mutex<Statistics>,
+    stat: ShardedStatistics,
 }
 
 impl LinHashCore {
     fn new(dir: &Path, ksize: usize, vsize: usize, pagesize: usize) -> Result<Self> {
         let primary_pages = Device::new(&dir.join("primary"), pagesize)?;
         let overflow_pages = Device::new(&dir.join("overflow"), pagesize)?;
+        // Wakeups are coalesced.
+        let (gc_tx, gc_rx) = crossbeam::channel::bounded(1);
+        let root = Root {
+            base_level: 1,
+            next_split_primary_page_id: 0,
+        };
 
         Ok(Self {
+            dir: dir.to_owned(),
+
             primary_pages,
-            root: RwLock::new(Root {
-                base_level: 1,
-                next_split_primary_page_id: 0,
-            }),
+            root: RwLock::new(root),
+            root_seq: RootSeq::new(&root),
             locks: lock::StripeLock::new(1024),
+            scale_lock_stripes: false,
 
             overflow_pages,
             next_overflow_id: AtomicU64::new(0),
+            free_list: Mutex::new(free_list::FreeList::default()),
+            n_reclaimed_overflow_pages: AtomicU64::new(0),
+            gc_tx,
+            gc_rx,
 
             max_kv_per_page: calc_max_kv_per_page(ksize, vsize),
             n_items: AtomicU64::new(0),
+            min_buckets: 2,
+
+            split_policy: SplitPolicy::default(),
+            split_threads: 1,
+            max_load_factor: None,
+            resize_events: Mutex::new(ResizeEvents::default()),
+            n_assisted_splits: AtomicU64::new(0),
+            n_failed_splits: AtomicU64::new(0),
+
+            damaged: Mutex::new(BTreeSet::new()),
+
+            dirty: AtomicBool::new(true),
+            checkpoint_lock: Mutex::new(()),
 
-            stat: Mutex::new(Statistics::default()),
+            stat: ShardedStatistics::new(),
         })
     }
 
-    fn open(dir: &Path, ksize: usize, vsize: usize, pagesize: usize) -> Result<Self> {
-        let mut db = Self::new(dir, ksize, vsize, pagesize)?;
+    fn open(dir: &Path, config: &LinHashConfig) -> Result<(Self, RecoveryReport)> {
+        let t = std::time::Instant::now();
+
+        config.validate()?;
+
+        let sb = meta::Superblock::read(dir)?;
+        if let Some(sb) = &sb {
+            sb.validate(config)?;
+        }
+        let checkpoint = meta::Checkpoint::read(dir)?;
+
+        let mut db = Self::new(dir, config.ksize, config.vsize, config.pagesize)?;
+        db.split_policy = config.split_policy;
+        db.split_threads = config.split_threads.max(1) as u64;
+        db.max_load_factor = config.max_load_factor;
+        db.locks = lock::StripeLock::new(config.lock_stripes);
+        db.scale_lock_stripes = config.scale_lock_stripes;
+
+        let clean_checkpoint = checkpoint.filter(|x| x.clean);
+
+        let mut report = match &clean_checkpoint {
+            // The checkpoint is trusted only if the database is complete.
+            Some(checkpoint) if sb.is_some() => util::Restore {
+                db: &mut db,
+                n_threads: 1,
+            }
+            .exec_checkpoint(checkpoint),
+            _ => util::Restore {
+                db: &mut db,
+                n_threads: config.recovery_threads,
+            }
+            .exec()?,
+        };
 
-        let n_primary_pages = util::Restore { db: &mut db }.exec()?;
+        let min_buckets = match &sb {
+            Some(sb) => sb.min_buckets,
+            None => config.calc_initial_buckets(db.max_kv_per_page),
+        };
 
         // Invariant: there are at least two valid primary pages.
-        if n_primary_pages < 2 {
-            util::Init { db: &mut db }.exec()?;
-            util::Restore { db: &mut db }.exec()?;
+        // The creation may have been interrupted before the superblock is written.
+        // Then the table is empty and created again.
+        let incomplete = sb.is_none() && report.n_items == 0 && report.n_buckets < min_buckets;
+        if report.n_buckets < 2 || incomplete {
+            util::Init {
+                db: &mut db,
+                n_buckets: min_buckets,
+            }
+            .exec()?;
+            report = util::Restore {
+                db: &mut db,
+                n_threads: 1,
+            }
+            .exec()?;
+            report.created = true;
+        }
+        // A database created before the superblock was introduced isn't resized.
+        db.min_buckets = min_buckets.min(report.n_buckets);
+
+        if db.scale_lock_stripes {
+            db.locks.grow(report.n_buckets, &db.root.write());
+        }
+
+        // A clean checkpoint which wasn't trusted is invalidated before the superblock makes it look trusted.
+        // Otherwise it stays on disk as `dirty` is already set and a crash brings it back.
+        if report.full_scan && clean_checkpoint.is_some() {
+            db.write_checkpoint(&db.root.read(), false)?;
+        }
+
+        // The superblock is written after the initial pages are persisted
+        // so the database is considered created only when it is complete.
+        // A directory created before the superblock was introduced gets one here too.
+        if sb.is_none() {
+            meta::Superblock {
+                ksize: config.ksize as u64,
+                vsize: config.vsize as u64,
+                pagesize: config.pagesize as u64,
+                hash_mode: HashMode::current(),
+                min_buckets: db.min_buckets,
+            }
+            .write(dir)?;
+        }
+
+        report.clean_shutdown = clean_checkpoint.is_some();
+        report.n_items_at_shutdown = clean_checkpoint.map(|x| x.n_items);
+        report.elapsed = t.elapsed();
+
+        db.root_seq.publish(&db.root.read());
+
+        // The clean checkpoint stays valid until the first modification.
+        if !report.full_scan {
+            db.dirty.store(false, Ordering::SeqCst);
+        }
+
+        Ok((db, report))
+    }
+
+    fn write_checkpoint(&self, root: &Root, clean: bool) -> Result<()> {
+        meta::Checkpoint {
+            clean,
+            n_items: self.n_items.load(Ordering::SeqCst),
+            base_level: root.base_level,
+            next_split_primary_page_id: root.next_split_primary_page_id,
+            next_overflow_id: self.next_overflow_id.load(Ordering::SeqCst),
+            free_overflow_ids: self.free_list.lock().reusable_ids(),
+            damaged_buckets: self.damaged.lock().iter().copied().collect(),
+        }
+        .write(&self.dir)
+    }
+
+    /// Invalidate the checkpoint on disk before the first modification after it.
+    /// The caller must hold the root lock so that this doesn't race with `flush`.
+    fn mark_dirty(&self, root: &Root) -> Result<()> {
+        if self.dirty.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let _guard = self.checkpoint_lock.lock();
+        if self.dirty.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.write_checkpoint(root, false)?;
+        self.dirty.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Persist all the pages and write a clean checkpoint.
+    fn flush(&self) -> Result<()> {
+        // Punch the freed pages before blocking the operations.
+        op::GC {
+            db: self,
+            root: self.root.write(),
+        }
+        .exec()?;
+
+        // Block all the operations so that the checkpoint is consistent with the pages.
+        let root = self.root.write();
+
+        // The pages freed or being reclaimed meanwhile become reusable
+        // as the pages which referenced them are persisted.
+        self.overflow_pages.flush()?;
+        self.primary_pages.flush()?;
+
+        let _guard = self.checkpoint_lock.lock();
+        self.write_checkpoint(&root, true)?;
+        self.dirty.store(false, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Allocate an overflow page, reusing a freed one if any.
+    fn alloc_overflow_id(&self) -> u64 {
+        if let Some(id) = self.free_list.lock().alloc() {
+            return id;
+        }
+        self.next_overflow_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Free the overflow pages which are no longer referenced.
+    /// They are reused after GC reclaims them.
+    fn release_overflow_pages(&self, ids: impl IntoIterator<Item = u64>) {
+        let n_pending = {
+            let mut free_list = self.free_list.lock();
+            free_list.release(ids);
+            free_list.n_pending()
+        };
+        if n_pending >= GC_BATCH {
+            self.gc_tx.try_send(()).ok();
+        }
+    }
+
+    /// Collect all the kv-pairs in the page chain and the ids of its overflow pages.
+    #[allow(clippy::type_complexity)]
+    fn collect_chain(&self, primary_page_id: u64) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Vec<u64>)> {
+        let mut kv_pairs = Vec::new();
+        let mut overflow_ids = Vec::new();
+
+        let mut cur_page = self.primary_pages.read_page(primary_page_id)?;
+        loop {
+            kv_pairs.extend(cur_page.kv_pairs);
+
+            match cur_page.overflow_id {
+                Some(id) => {
+                    overflow_ids.push(id);
+                    cur_page = self.overflow_pages.read_page(id)?;
+                }
+                None => break,
+            }
+        }
+
+        Ok((kv_pairs, overflow_ids))
+    }
+
+    /// Pack the kv-pairs into a new page chain in the minimal number of pages.
+    /// The overflow pages are allocated but not written.
+    fn build_chain(
+        &self,
+        primary_page_id: u64,
+        locallevel: u8,
+        kv_pairs: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> Vec<(PageId, Page)> {
+        let max_kv_per_page = self.max_kv_per_page as usize;
+
+        let mut pages = Vec::new();
+        let mut kv_pairs = kv_pairs.into_iter().peekable();
+        loop {
+            let mut page = Page::new();
+            for (k, v) in kv_pairs.by_ref().take(max_kv_per_page) {
+                page.insert(k, v);
+            }
+            pages.push(page);
+
+            if kv_pairs.peek().is_none() {
+                break;
+            }
+        }
+        pages[0].locallevel = Some(locallevel);
+
+        let n_overflow_pages = pages.len() - 1;
+        let mut page_ids = vec![PageId::Primary(primary_page_id)];
+        for page in pages.iter_mut().take(n_overflow_pages) {
+            let id = self.alloc_overflow_id();
+            page.overflow_id = Some(id);
+            page_ids.push(PageId::Overflow(id));
+        }
+
+        page_ids.into_iter().zip(pages).collect()
+    }
+
+    /// Write the kv-pairs as a new page chain in the minimal number of pages.
+    /// The new overflow pages are persisted before the primary page is overwritten
+    /// so the old chain stays intact until then.
+    fn write_chain(
+        &self,
+        primary_page_id: u64,
+        locallevel: u8,
+        kv_pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let chain = self.build_chain(primary_page_id, locallevel, kv_pairs);
+
+        // Write from the tail.
+        for (page_id, page) in chain.iter().rev() {
+            match *page_id {
+                PageId::Primary(id) => {
+                    if chain.len() > 1 {
+                        self.overflow_pages.flush()?;
+                    }
+                    self.primary_pages.write_page(id, page)?;
+                }
+                PageId::Overflow(id) => self.overflow_pages.write_page(id, page)?,
+            }
         }
 
-        Ok(db)
+        Ok(())
     }
 
     // The key must be at least 64 bits.
@@ -263,6 +786,51 @@ impl LinHashCore {
         xxhash_rust::xxh3::xxh3_64(key)
     }
 
+    /// Split the buckets from the split pointer in a batch and return the number of buckets split.
+    /// At most `n` buckets are split and the batch doesn't cross the level.
+    /// Returns 0 if the bucket at the split pointer is damaged.
+    fn split(&self, n: u64) -> Result<u64> {
+        let root = self.root.upgradable_read();
+
+        // The buckets in a batch are split at the same level.
+        // Consecutive buckets no more than the stripes are in distinct stripes
+        // so the batch doesn't lock a stripe twice.
+        let split_id = root.next_split_primary_page_id;
+        let mut n = n
+            .min(self.split_threads)
+            .min(self.locks.n_stripes())
+            .min((1 << root.base_level) - split_id);
+        // A damaged bucket can't be split so the split pointer stops there.
+        if let Some(&id) = self.damaged.lock().range(split_id..split_id + n).next() {
+            n = id - split_id;
+        }
+        if n == 0 {
+            return Ok(0);
+        }
+        let buckets = (split_id..split_id + n)
+            .map(|id| {
+                let chain_id = root.calc_page_chain_id(id);
+                assert_eq!(chain_id.primary_page_id, id);
+                (chain_id, self.locks.selective_lock(id))
+            })
+            .collect();
+
+        let res = op::Split {
+            db: self,
+            root,
+            buckets,
+            n_threads: n as usize,
+        }
+        .exec();
+        // Neither the split thread nor the inserts helping it can return the error to anyone.
+        if res.is_err() {
+            self.n_failed_splits.fetch_add(1, Ordering::SeqCst);
+        }
+        res?;
+
+        Ok(n)
+    }
+
     fn load_factor(&self) -> f64 {
         let n_primary_pages = self.root.read().calc_n_pages();
         let max_items = n_primary_pages * self.max_kv_per_page as u64;
@@ -270,24 +838,181 @@ impl LinHashCore {
     }
 }
 
+/// When to split the next bucket.
+/// Splitting earlier keeps the chains short at the cost of space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitPolicy {
+    /// Split when the load factor exceeds the threshold, which must be positive.
+    LoadFactor(f64),
+    /// Split whenever an insert allocates an overflow page, as in Litwin's original scheme.
+    OverflowAllocation,
+    /// Split when an insert finds a chain longer than this number of pages including the primary page.
+    ChainLength(usize),
+}
+
+impl Default for SplitPolicy {
+    fn default() -> Self {
+        Self::LoadFactor(0.8)
+    }
+}
+
+impl SplitPolicy {
+    fn calls_for_split(&self, evt: &ResizeEvent) -> bool {
+        match (*self, *evt) {
+            (
+                Self::OverflowAllocation,
+                ResizeEvent::Insert {
+                    overflow_allocated, ..
+                },
+            ) => overflow_allocated,
+            (Self::ChainLength(n), ResizeEvent::Insert { chain_len, .. }) => chain_len > n,
+            _ => false,
+        }
+    }
+
+    /// The number of splits to do given `n_events` events which call for a split.
+    fn calc_n_splits(&self, db: &LinHashCore, n_events: u64) -> u64 {
+        match *self {
+            // Split until the load factor is at most the threshold.
+            Self::LoadFactor(threshold) => {
+                let n_items = db.n_items.load(Ordering::SeqCst);
+                let per_bucket = db.max_kv_per_page as f64 * threshold;
+                let n_pages = (n_items as f64 / per_bucket).ceil() as u64;
+                n_pages.saturating_sub(db.root.read().calc_n_pages())
+            }
+            _ => n_events,
+        }
+    }
+}
+
 #[derive(typed_builder::TypedBuilder, Clone)]
 pub struct LinHashConfig {
     pub ksize: usize,
     pub vsize: usize,
     #[builder(default = 4096)]
     pub pagesize: usize,
+    /// The number of threads to scan the table on recovery after a crash.
+    #[builder(default = std::thread::available_parallelism().map_or(1, |n| n.get()))]
+    pub recovery_threads: usize,
+    /// The maximum number of threads to read the buckets in `get_many`.
+    /// Reading the buckets concurrently is opt-in: by default they are read one by one on the calling thread.
+    /// Above 1, the threads are spawned for each call, so it only pays off for large batches on slow devices.
+    /// The statistics are kept in a fixed number of shards so the spawned threads don't add to them.
+    #[builder(default = 1)]
+    pub read_threads: usize,
+    #[builder(default)]
+    pub split_policy: SplitPolicy,
+    /// The number of items the table is sized for on creation.
+    /// All the buckets are created up front so that loading this many items doesn't split.
+    /// Ignored when opening an existing database.
+    #[builder(default)]
+    pub initial_capacity: u64,
+    /// The maximum number of consecutive buckets split in parallel.
+    /// It must not exceed `lock_stripes`.
+    #[builder(default = 1)]
+    pub split_threads: usize,
+    /// The last bucket is merged into its buddy when deletes bring the load factor below this.
+    /// 0 disables merging. It must be below the threshold of `SplitPolicy::LoadFactor`.
+    #[builder(default = 0.3)]
+    pub merge_load_factor: f64,
+    /// The hard limit of the load factor.
+    /// When the split thread falls behind and the load factor exceeds this,
+    /// inserts split buckets by themselves before returning so they are slowed down to the pace of splitting.
+    /// It should be above the threshold of the split policy. `None` disables the limit.
+    #[builder(default = Some(2.0))]
+    pub max_load_factor: Option<f64>,
+    /// The number of lock stripes. Buckets sharing a stripe block each other.
+    #[builder(default = 1024)]
+    pub lock_stripes: usize,
+    /// Double the lock stripes whenever the table outgrows them
+    /// so that each bucket has its own stripe.
+    #[builder(default)]
+    pub scale_lock_stripes: bool,
+    /// The interval to reclaim freed overflow pages. `None` disables the periodic GC.
+    /// GC also runs when many pages are freed and on flush.
+    #[builder(default = Some(Duration::from_secs(1)))]
+    pub gc_interval: Option<Duration>,
+}
+
+impl LinHashConfig {
+    fn calc_initial_buckets(&self, max_kv_per_page: u16) -> u64 {
+        let load_factor = match self.split_policy {
+            SplitPolicy::LoadFactor(x) => x,
+            _ => 0.8,
+        };
+        let per_bucket = max_kv_per_page as f64 * load_factor;
+        let n = (self.initial_capacity as f64 / per_bucket).ceil() as u64;
+        n.max(2)
+    }
+
+    /// Reject the settings which can't work together.
+    fn validate(&self) -> Result<()> {
+        let invalid = |msg: String| -> Result<()> {
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg).into())
+        };
+
+        // A split batch locks a stripe for each bucket.
+        if self.lock_stripes.max(1) < self.split_threads.max(1) {
+            return invalid(format!(
+                "lock_stripes ({}) is less than split_threads ({})",
+                self.lock_stripes, self.split_threads
+            ));
+        }
+
+        if self.merge_load_factor.is_nan() || self.merge_load_factor < 0.0 {
+            return invalid(format!(
+                "merge_load_factor {} is negative",
+                self.merge_load_factor
+            ));
+        }
+        if let SplitPolicy::LoadFactor(threshold) = self.split_policy {
+            // The split thread would never be satisfied.
+            if threshold.is_nan() || threshold <= 0.0 {
+                return invalid(format!("split load factor {threshold} is not positive"));
+            }
+            // A merge would call for the split undoing it.
+            if self.merge_load_factor >= threshold {
+                return invalid(format!(
+                    "merge_load_factor {} is not below the split load factor {threshold}",
+                    self.merge_load_factor
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load the config of an existing database from its superblock.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let Some(sb) = meta::Superblock::read(dir)? else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no database found in {}", dir.display()),
+            )
+            .into());
+        };
+        Ok(Self::builder()
+            .ksize(sb.ksize as usize)
+            .vsize(sb.vsize as usize)
+            .pagesize(sb.pagesize as usize)
+            .build())
+    }
 }
 
 pub struct LinHash {
     core: Arc<LinHashCore>,
+    report: RecoveryReport,
     gc_shutdown_tx: Option<crossbeam::channel::Sender<()>>,
     split_tx: Option<crossbeam::channel::Sender<()>>,
     spawn_handles: Vec<std::thread::JoinHandle<()>>,
+    read_threads: usize,
 }
 
 impl LinHash {
+    /// Open the database in `dir`, creating it if it doesn't exist.
+    /// An existing database must have been created with the same config.
     pub fn open(dir: &Path, settings: LinHashConfig) -> Result<Self> {
-        let core = LinHashCore::open(dir, settings.ksize, settings.vsize, settings.pagesize)?;
+        let (core, report) = LinHashCore::open(dir, &settings)?;
         let core = Arc::new(core);
 
         let mut spawn_handles = vec![];
@@ -295,47 +1020,73 @@ impl LinHash {
         let (gc_shutdown_tx, gc_shutdown_rx) = crossbeam::channel::unbounded::<()>();
         spawn_handles.push(std::thread::spawn({
             let core = Arc::clone(&core);
+            let gc_interval = settings.gc_interval;
             move || {
+                let gc = || {
+                    // Don't block the operations if there is nothing to reclaim.
+                    if core.free_list.lock().n_pending() == 0 {
+                        return;
+                    }
+                    op::GC {
+                        db: &core,
+                        root: core.root.write(),
+                    }
+                    .exec()
+                    .ok();
+                };
                 loop {
+                    let tick = match gc_interval {
+                        Some(interval) => crossbeam::channel::after(interval),
+                        None => crossbeam::channel::never(),
+                    };
                     crossbeam::select! {
                         // GC thread should be dropped when LinHash instance is dropped.
                         // Without explicit termination, it runs forever and destroys the database.
                         recv(gc_shutdown_rx) -> _ => break,
-                        recv(crossbeam::channel::after(Duration::from_secs(1))) -> _ => {
-                            let root = core.root.read();
-                            op::GC { db: &core, root: *root }.exec().ok();
-                        }
+                        recv(core.gc_rx) -> _ => gc(),
+                        recv(tick) -> _ => gc(),
                     }
                 }
             }
         }));
 
-        let (tx, rx) = crossbeam::channel::unbounded();
+        // Wakeups are coalesced.
+        let (tx, rx) = crossbeam::channel::bounded(1);
         spawn_handles.push(std::thread::spawn({
             let core = Arc::clone(&core);
+            let merge_load_factor = settings.merge_load_factor;
             move || {
-                while let Ok(()) = rx.recv() {
-                    if core.load_factor() > 0.8 {
-                        let root = core.root.read();
-
-                        let chain_id = {
-                            let split_id = root.next_split_primary_page_id;
-                            let chain_id = root.calc_page_chain_id(split_id);
-                            assert_eq!(chain_id.primary_page_id, split_id);
-                            chain_id
-                        };
-
-                        let resp = op::Split {
-                            db: &core,
-                            root,
-                            chain_id,
-                            lock: core.locks.selective_lock(chain_id.primary_page_id),
+                while rx.recv().is_ok() {
+                    // Handle the accumulated events at once so that the splits are batched.
+                    let evts = std::mem::take(&mut *core.resize_events.lock());
+
+                    // Only deletes shrink the table so that merges and splits don't alternate.
+                    // The table never shrinks below the initial buckets.
+                    // Only this thread shrinks the table.
+                    if evts.deleted {
+                        while core.load_factor() < merge_load_factor
+                            && core.root.read().calc_n_pages() > core.min_buckets
+                        {
+                            let root = core.root.write();
+                            let res = op::Merge { db: &core, root }.exec();
+                            if res.is_err() {
+                                break;
+                            }
                         }
-                        .exec();
+                    }
 
-                        if resp.is_ok() {
-                            core.root.write().advance_split_pointer();
+                    // The number of splits is calculated again after each batch
+                    // because inserts may have split some buckets meanwhile.
+                    let mut n_events = evts.n_split;
+                    loop {
+                        let n_splits = core.split_policy.calc_n_splits(&core, n_events);
+                        if n_splits == 0 {
+                            break;
                         }
+                        let Ok(n @ 1..) = core.split(n_splits) else {
+                            break;
+                        };
+                        n_events = n_events.saturating_sub(n);
                     }
                 }
             }
@@ -343,47 +1094,269 @@ impl LinHash {
 
         Ok(Self {
             core,
+            report,
             gc_shutdown_tx: Some(gc_shutdown_tx),
             split_tx: Some(tx),
             spawn_handles,
+            read_threads: settings.read_threads,
         })
     }
 
+    /// Build a new database in `dir` from the kv-pairs and open it.
+    /// The table is sized for the number of pairs loaded or `config.initial_capacity`,
+    /// whichever is larger, and each page is written once.
+    /// A later pair overwrites an earlier one with the same key.
+    pub fn bulk_load(
+        dir: &Path,
+        config: LinHashConfig,
+        kv_pairs: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> Result<Self> {
+        config.validate()?;
+        util::BulkLoad {
+            dir,
+            config: &config,
+        }
+        .exec(kv_pairs)?;
+        Self::open(dir, config)
+    }
+
+    /// Open an existing database with the config stored in the superblock.
+    pub fn open_existing(dir: &Path) -> Result<Self> {
+        let config = LinHashConfig::load(dir)?;
+        Self::open(dir, config)
+    }
+
     pub fn len(&self) -> u64 {
         self.core.n_items.load(Ordering::SeqCst)
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        loop {
-            let root = self.core.root.read();
-            let chain_id = root.calc_page_chain_id(self.core.calc_hash(key));
-            let val = op::Get {
+        self.get_with(key, |v| v.map(|v| v.to_vec()))
+    }
+
+    /// Call `f` with the value borrowed from the page read, without copying it.
+    pub fn get_with<R>(&self, key: &[u8], f: impl FnOnce(Option<&[u8]>) -> R) -> Result<R> {
+        let page = loop {
+            let Some(root) = self.core.root_seq.read() else {
+                // Wait for the writer holding the root lock.
+                drop(self.core.root.read());
+                continue;
+            };
+            let chain_id = root.root.calc_page_chain_id(self.core.calc_hash(key));
+            let page = op::Get {
                 db: &self.core,
                 chain_id,
                 root,
-                lock: self.core.locks.read_lock(chain_id.primary_page_id),
+                lock: self.core.locks.optimistic_read(chain_id.primary_page_id),
             }
             .exec(key);
 
-            match val {
-                Ok(v) => return Ok(v),
-                Err(Error::LocalLevelMismatch) => continue,
-                e => return e,
+            match page {
+                Ok(page) => break page,
+                Err(Error::LocalLevelMismatch | Error::VersionMismatch) => continue,
+                Err(e) => return Err(e),
+            }
+        };
+
+        Ok(f(page.as_ref().and_then(|page| page.get_value(key))))
+    }
+
+    /// Look up the keys at once. The values are returned in the order of the keys.
+    /// The keys in the same bucket are looked up by reading the page chain once
+    /// and the buckets are read concurrently by up to `read_threads` threads spawned for the call if it's more than one.
+    pub fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut vals = vec![None; keys.len()];
+
+        // The indices of the keys not looked up yet.
+        let mut pending: Vec<usize> = (0..keys.len()).collect();
+        while !pending.is_empty() {
+            // The buckets are checked again when read.
+            let root = match self.core.root_seq.read() {
+                Some(root) => root.root,
+                None => *self.core.root.read(),
+            };
+            let mut buckets: HashMap<PageChainId, Vec<usize>> = HashMap::new();
+            for i in pending {
+                let chain_id = root.calc_page_chain_id(self.core.calc_hash(keys[i]));
+                buckets.entry(chain_id).or_default().push(i);
+            }
+            let buckets: Vec<(PageChainId, Vec<usize>)> = buckets.into_iter().collect();
+
+            let get_bucket = |(chain_id, indices): &(PageChainId, Vec<usize>)| {
+                let keys: Vec<&[u8]> = indices.iter().map(|&i| keys[i]).collect();
+                self.get_bucket(*chain_id, &keys)
+            };
+            let n_threads = self.read_threads.clamp(1, buckets.len().max(1));
+            let results: Vec<_> = if n_threads == 1 {
+                buckets.iter().map(get_bucket).collect()
+            } else {
+                let chunk_size = buckets.len().div_ceil(n_threads);
+                std::thread::scope(|s| {
+                    let handles: Vec<_> = buckets
+                        .chunks(chunk_size)
+                        .map(|chunk| {
+                            s.spawn(move || chunk.iter().map(get_bucket).collect::<Vec<_>>())
+                        })
+                        .collect();
+                    handles
+                        .into_iter()
+                        .flat_map(|handle| handle.join().unwrap())
+                        .collect()
+                })
+            };
+
+            pending = Vec::new();
+            for ((_, indices), res) in buckets.into_iter().zip(results) {
+                match res? {
+                    Some(bucket_vals) => {
+                        for (i, v) in indices.into_iter().zip(bucket_vals) {
+                            vals[i] = v;
+                        }
+                    }
+                    // The table was resized. The keys are grouped again.
+                    None => pending.extend(indices),
+                }
             }
         }
+
+        Ok(vals)
     }
 
-    pub fn list(&self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> {
-        let root = self.core.root.write();
+    // Returns `None` if the keys aren't in the bucket any more.
+    fn get_bucket(
+        &self,
+        chain_id: PageChainId,
+        keys: &[&[u8]],
+    ) -> Result<Option<Vec<Option<Vec<u8>>>>> {
+        loop {
+            let Some(root) = self.core.root_seq.read() else {
+                drop(self.core.root.read());
+                continue;
+            };
+            let moved = keys
+                .iter()
+                .any(|key| root.root.calc_page_chain_id(self.core.calc_hash(key)) != chain_id);
+            if moved {
+                return Ok(None);
+            }
+
+            let vals = op::Get {
+                db: &self.core,
+                chain_id,
+                root,
+                lock: self.core.locks.optimistic_read(chain_id.primary_page_id),
+            }
+            .exec_many(keys);
+
+            match vals {
+                Ok(vals) => return Ok(Some(vals)),
+                Err(Error::LocalLevelMismatch | Error::VersionMismatch) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Copy the value into `buf` which must be as long as the value.
+    /// Returns false if the key isn't found.
+    pub fn get_into(&self, key: &[u8], buf: &mut [u8]) -> Result<bool> {
+        self.get_with(key, |v| match v {
+            Some(v) if v.len() == buf.len() => {
+                buf.copy_from_slice(v);
+                Ok(true)
+            }
+            Some(v) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "buffer of {} bytes for value of {} bytes",
+                    buf.len(),
+                    v.len()
+                ),
+            )
+            .into()),
+            None => Ok(false),
+        })?
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        self.get_with(key, |v| v.is_some())
+    }
+
+    /// The iterator stops after yielding an error.
+    /// Writers aren't blocked while the iterator is held.
+    /// A pair present during the whole iteration is yielded exactly once
+    /// while a pair inserted or deleted meanwhile may or may not be.
+    pub fn list(&self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> {
+        let level = self.core.root.read().base_level;
         op::List {
             db: &self.core,
-            root,
+            level,
+            groups: 0..1 << level,
+        }
+        .exec()
+    }
+
+    /// Split the table into `k` disjoint partitions which can be scanned in parallel.
+    /// Together the partitions yield what `list` does.
+    /// The partitions are fixed when this is called so splits during the scan don't matter.
+    /// `k` is at most `MAX_PARTITIONS`.
+    pub fn partitions(&self, k: usize) -> Vec<Partition<'_>> {
+        // Far more partitions than threads would only be overhead.
+        let k = (k as u64).clamp(1, MAX_PARTITIONS);
+        // Each partition has at least a group.
+        let level = self
+            .core
+            .root
+            .read()
+            .base_level
+            .max(k.next_power_of_two().ilog2() as u8);
+        op::Partition::split(&self.core, level, k)
+    }
+
+    /// Visit all the pairs without allocating them.
+    /// The slices are borrowed from the pages read and valid only during the call.
+    /// Like `list`, a pair present during the whole visit is visited exactly once and writers aren't blocked.
+    /// Stops when `f` breaks and returns its value.
+    pub fn for_each<B>(
+        &self,
+        f: impl FnMut(&[u8], &[u8]) -> ControlFlow<B>,
+    ) -> Result<ControlFlow<B>> {
+        op::ForEach { db: &self.core }.exec(f)
+    }
+
+    /// `for_each` visiting only the keys.
+    pub fn for_each_key<B>(
+        &self,
+        mut f: impl FnMut(&[u8]) -> ControlFlow<B>,
+    ) -> Result<ControlFlow<B>> {
+        self.for_each(|k, _| f(k))
+    }
+
+    /// `for_each` visiting only the values.
+    pub fn for_each_value<B>(
+        &self,
+        mut f: impl FnMut(&[u8]) -> ControlFlow<B>,
+    ) -> Result<ControlFlow<B>> {
+        self.for_each(|_, v| f(v))
+    }
+
+    /// Return at most `limit` pairs from the cursor with the cursor to resume from.
+    /// No cursor is returned when the scan reaches the end.
+    /// The cursor can be persisted to page through the table across requests.
+    pub fn scan_from(&self, cursor: ScanCursor, limit: usize) -> Result<ScanBatch> {
+        op::Scan {
+            db: &self.core,
+            cursor,
+            limit,
         }
         .exec()
     }
 
     pub fn insert(&self, key: Vec<u8>, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        let old = loop {
+        let (old, evt) = loop {
             let root = self.core.root.read();
             let chain_id = root.calc_page_chain_id(self.core.calc_hash(&key));
             let old = op::Insert {
@@ -395,20 +1368,29 @@ impl LinHash {
             .exec(key.clone(), value.clone());
 
             match old {
-                Ok(old) => break old,
+                Ok(x) => break x,
                 Err(Error::LocalLevelMismatch) => continue,
-                e => return e,
+                Err(e) => return Err(e),
             }
         };
 
         if old.is_none() {
-            self.core.stat.lock().push(OpEvent::InsertMiss);
-            self.core.n_items.fetch_add(1, Ordering::SeqCst);
+            self.core.stat.push(OpEvent::InsertMiss);
         } else {
-            self.core.stat.lock().push(OpEvent::InsertHit);
+            self.core.stat.push(OpEvent::InsertHit);
         }
 
-        self.split_tx.as_ref().unwrap().send(()).ok();
+        self.notify(evt);
+
+        // Backpressure: help splitting while the split thread is behind.
+        if let Some(max_load_factor) = self.core.max_load_factor {
+            while self.core.load_factor() > max_load_factor {
+                let Ok(n @ 1..) = self.core.split(self.core.split_threads) else {
+                    break;
+                };
+                self.core.n_assisted_splits.fetch_add(n, Ordering::SeqCst);
+            }
+        }
 
         Ok(old)
     }
@@ -421,7 +1403,7 @@ impl LinHash {
                 db: &self.core,
                 chain_id,
                 root,
-                lock: self.core.locks.exclusive_lock(chain_id.primary_page_id),
+                lock: self.core.locks.selective_lock(chain_id.primary_page_id),
             }
             .exec(key);
 
@@ -433,23 +1415,69 @@ impl LinHash {
         };
 
         if old.is_some() {
-            self.core.n_items.fetch_sub(1, Ordering::SeqCst);
-            self.core.stat.lock().push(OpEvent::DeleteHit);
+            self.core.stat.push(OpEvent::DeleteHit);
         } else {
-            self.core.stat.lock().push(OpEvent::DeleteMiss);
+            self.core.stat.push(OpEvent::DeleteMiss);
         }
 
+        self.notify(ResizeEvent::Delete);
+
         Ok(old)
     }
 
+    // Wake up the split thread.
+    fn notify(&self, evt: ResizeEvent) {
+        {
+            let mut evts = self.core.resize_events.lock();
+            if self.core.split_policy.calls_for_split(&evt) {
+                evts.n_split += 1;
+            }
+            if matches!(evt, ResizeEvent::Delete) {
+                evts.deleted = true;
+            }
+        }
+        self.split_tx.as_ref().unwrap().try_send(()).ok();
+    }
+
+    /// Persist all the changes and checkpoint the in-memory state
+    /// so that the next open doesn't need to scan the table.
+    /// Operations are blocked while flushing.
     pub fn flush(&self) -> Result<()> {
-        self.core.overflow_pages.flush()?;
-        self.core.primary_pages.flush()?;
-        Ok(())
+        self.core.flush()
+    }
+
+    /// Reclaim the freed overflow pages now.
+    /// Operations are blocked only while the freed pages are taken.
+    pub fn gc(&self) -> Result<()> {
+        op::GC {
+            db: &self.core,
+            root: self.core.root.write(),
+        }
+        .exec()
+    }
+
+    /// What recovery found when the database was opened.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.report
+    }
+
+    /// The primary page ids of the buckets found unreadable on recovery.
+    /// Operations on these buckets return `Error::Corruption` while the rest of the table is served.
+    pub fn damaged_buckets(&self) -> Vec<u64> {
+        self.core.damaged.lock().iter().copied().collect()
     }
 
     pub fn stat(&self) -> Statistics {
-        *self.core.stat.lock()
+        let n_events = self.core.resize_events.lock().n_split;
+        let mut stat = self.core.stat.sum();
+        stat.load_factor = self.core.load_factor();
+        stat.n_pending_splits = self.core.split_policy.calc_n_splits(&self.core, n_events);
+        stat.n_assisted_splits = self.core.n_assisted_splits.load(Ordering::SeqCst);
+        stat.n_failed_splits = self.core.n_failed_splits.load(Ordering::SeqCst);
+        stat.n_reclaimed_overflow_pages =
+            self.core.n_reclaimed_overflow_pages.load(Ordering::SeqCst);
+        stat.lock_stat = self.core.locks.stat();
+        stat
     }
 }
 
@@ -463,5 +1491,44 @@ impl Drop for LinHash {
         for handle in self.spawn_handles.drain(..) {
             handle.join().ok();
         }
+
+        self.core.flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sharded_statistics() {
+        let stat1 = ShardedStatistics::new();
+        let stat2 = ShardedStatistics::new();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        stat1.push(OpEvent::GetHit(1));
+                        stat2.push(OpEvent::GetMiss(2));
+                    }
+                });
+            }
+        });
+        stat1.push(OpEvent::GetHit(1));
+
+        // Many short-lived threads don't add shards.
+        for _ in 0..2 * N_STAT_SHARDS {
+            std::thread::scope(|s| {
+                s.spawn(|| stat2.push(OpEvent::GetMiss(2)));
+            });
+        }
+        assert_eq!(stat2.shards.len(), N_STAT_SHARDS);
+
+        let sum = stat1.sum();
+        assert_eq!(sum.n_get_hit, 401);
+        assert_eq!(sum.n_get_miss, 0);
+        let sum = stat2.sum();
+        assert_eq!(sum.n_get_miss, 400 + 2 * N_STAT_SHARDS as u64);
+        assert_eq!(sum.n_get_miss_hops, 2 * sum.n_get_miss);
     }
 }
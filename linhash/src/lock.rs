@@ -1,85 +1,270 @@
 use super::*;
 
+use crossbeam::utils::CachePadded;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicUsize;
+
 pub struct ReadLockGuard<'a>(#[allow(unused)] RwLockReadGuard<'a, ()>);
 
-pub struct SelectiveLockGuard<'a>(
-    #[allow(unused)] RwLockReadGuard<'a, ()>,
-    #[allow(unused)] MutexGuard<'a, ()>,
-);
+/// Doesn't lock the stripe but remembers its sequence number
+/// so that the reader can validate what it has read afterward.
+/// Acquiring it doesn't write shared memory.
+pub struct OptimisticReadGuard<'a> {
+    stripe: &'a Stripe,
+    start_seq: u64,
+}
+
+impl OptimisticReadGuard<'_> {
+    /// True if no writer under `SelectiveLockGuard::write_seq` overlapped
+    /// since the guard was acquired.
+    pub fn validate(&self) -> bool {
+        self.start_seq.is_multiple_of(2) && self.stripe.seq.load(Ordering::SeqCst) == self.start_seq
+    }
+
+    /// Wait until the writer under `SelectiveLockGuard::write_seq`, if any, releases the stripe.
+    /// The other holders of the stripe aren't waited for because they don't fail the readers.
+    pub fn wait(&self) {
+        if !self.stripe.seq.load(Ordering::SeqCst).is_multiple_of(2) {
+            drop(self.stripe.mutex.lock());
+        }
+    }
+}
+
+pub struct SelectiveLockGuard<'a> {
+    _guards: (RwLockReadGuard<'a, ()>, MutexGuard<'a, ()>),
+    seq: &'a AtomicU64,
+}
+
+impl<'a> SelectiveLockGuard<'a> {
+    fn new(stripe: &'a Stripe, g1: RwLockReadGuard<'a, ()>, g2: MutexGuard<'a, ()>) -> Self {
+        Self {
+            _guards: (g1, g2),
+            seq: &stripe.seq,
+        }
+    }
+
+    /// Mark the stripe as being modified until the returned guard is dropped.
+    /// Readers overlapping the modification fail to validate.
+    pub fn write_seq(&self) -> SeqWriteGuard<'_> {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        SeqWriteGuard(self.seq)
+    }
+}
+
+pub struct SeqWriteGuard<'a>(&'a AtomicU64);
+
+impl Drop for SeqWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
 
 pub struct ExclusiveLockGuard<'a>(#[allow(unused)] RwLockWriteGuard<'a, ()>);
 
+// The locks of a stripe.
+// The counters are kept in the cache line of the locks which the acquirer writes anyway
+// so that counting doesn't add writes to a line shared by all the stripes.
+struct Stripe {
+    rwlock: RwLock<()>,
+    mutex: Mutex<()>,
+    // The page id the lock was last acquired for.
+    holder: AtomicU64,
+    // Odd while a writer modifies the stripe's buckets in a way readers can't see consistently.
+    seq: AtomicU64,
+
+    n_acquired: AtomicU64,
+    n_contended: AtomicU64,
+    n_collisions: AtomicU64,
+}
+
+impl Stripe {
+    fn new() -> CachePadded<Self> {
+        CachePadded::new(Self {
+            rwlock: RwLock::new(()),
+            mutex: Mutex::new(()),
+            holder: AtomicU64::new(u64::MAX),
+            seq: AtomicU64::new(0),
+
+            n_acquired: AtomicU64::new(0),
+            n_contended: AtomicU64::new(0),
+            n_collisions: AtomicU64::new(0),
+        })
+    }
+}
+
+// The stripes are allocated in segments so they can be added without moving the existing ones.
+// The first segment has the initial stripes and each of the rest doubles the stripes.
+const N_SEGMENTS: usize = 48;
+
+/// The counters of the lock acquisitions.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LockStat {
+    /// The number of stripes.
+    pub n_stripes: u64,
+    /// The number of locks acquired.
+    pub n_acquired: u64,
+    /// The number of locks which had to wait because the stripe was held.
+    pub n_contended: u64,
+    /// The number of contended locks whose stripe was held for another page.
+    /// These waits are caused only by sharing the stripe.
+    pub n_collisions: u64,
+}
+
 pub struct StripeLock {
-    n: usize,
-    rwlocks: Vec<RwLock<()>>,
-    mutexes: Vec<Mutex<()>>,
+    n_initial: usize,
+    n: AtomicUsize,
+    segments: [OnceLock<Box<[CachePadded<Stripe>]>>; N_SEGMENTS],
 }
 
 impl StripeLock {
     pub fn new(n: usize) -> Self {
-        let mut rwlocks = Vec::with_capacity(n);
-        for _ in 0..n {
-            rwlocks.push(RwLock::new(()));
+        let n = n.max(1);
+        let segments = std::array::from_fn(|_| OnceLock::new());
+        segments[0].get_or_init(|| (0..n).map(|_| Stripe::new()).collect());
+        Self {
+            n_initial: n,
+            n: AtomicUsize::new(n),
+            segments,
         }
-        let mut mutexes = Vec::with_capacity(n);
-        for _ in 0..n {
-            mutexes.push(Mutex::new(()));
+    }
+
+    /// Double the stripes until there are at least `n` of them.
+    /// The root write lock ensures that no stripe is held
+    /// because the stripes are locked only under the root lock.
+    pub fn grow(&self, n: u64, _root: &RwLockWriteGuard<'_, Root>) {
+        let mut cur = self.n.load(Ordering::SeqCst);
+        while (cur as u64) < n {
+            let i = (cur / self.n_initial).ilog2() as usize + 1;
+            if i >= N_SEGMENTS {
+                break;
+            }
+            self.segments[i].get_or_init(|| (0..cur).map(|_| Stripe::new()).collect());
+            cur *= 2;
         }
-        Self {
-            n,
-            rwlocks,
-            mutexes,
+        self.n.store(cur, Ordering::SeqCst);
+    }
+
+    pub fn n_stripes(&self) -> u64 {
+        self.n.load(Ordering::SeqCst) as u64
+    }
+
+    fn stripe(&self, id: u64) -> &Stripe {
+        let b = (id % self.n.load(Ordering::SeqCst) as u64) as usize;
+        if b < self.n_initial {
+            return &self.segments[0].get().unwrap()[b];
         }
+        // Segment i >= 1 starts at n_initial * 2^(i-1).
+        let i = (b / self.n_initial).ilog2() as usize + 1;
+        let start = self.n_initial << (i - 1);
+        &self.segments[i].get().unwrap()[b - start]
     }
 
+    // Try to lock first to count the contention.
+    fn acquire<'a, G>(
+        &'a self,
+        id: u64,
+        try_lock: impl FnOnce(&'a Stripe) -> Option<G>,
+        lock: impl FnOnce(&'a Stripe) -> G,
+    ) -> G {
+        let stripe = self.stripe(id);
+        let g = match try_lock(stripe) {
+            Some(g) => g,
+            None => {
+                stripe.n_contended.fetch_add(1, Ordering::Relaxed);
+                if stripe.holder.load(Ordering::Relaxed) != id {
+                    stripe.n_collisions.fetch_add(1, Ordering::Relaxed);
+                }
+                lock(stripe)
+            }
+        };
+        stripe.n_acquired.fetch_add(1, Ordering::Relaxed);
+        stripe.holder.store(id, Ordering::Relaxed);
+        g
+    }
+
+    pub fn stat(&self) -> LockStat {
+        let mut stat = LockStat {
+            n_stripes: self.n.load(Ordering::SeqCst) as u64,
+            ..Default::default()
+        };
+        for stripe in self
+            .segments
+            .iter()
+            .filter_map(|x| x.get())
+            .flat_map(|x| x.iter())
+        {
+            stat.n_acquired += stripe.n_acquired.load(Ordering::Relaxed);
+            stat.n_contended += stripe.n_contended.load(Ordering::Relaxed);
+            stat.n_collisions += stripe.n_collisions.load(Ordering::Relaxed);
+        }
+        stat
+    }
+
+    #[allow(unused)]
     pub fn read_lock(&self, id: u64) -> ReadLockGuard<'_> {
-        let b = (id as usize) % self.n;
-        ReadLockGuard(self.rwlocks[b].read())
+        self.acquire(
+            id,
+            |x| x.rwlock.try_read().map(ReadLockGuard),
+            |x| ReadLockGuard(x.rwlock.read()),
+        )
     }
 
+    #[allow(unused)]
     pub fn try_read_lock(&self, id: u64) -> Option<ReadLockGuard<'_>> {
-        let b = (id as usize) % self.n;
-        match self.rwlocks[b].try_read() {
-            Some(g) => Some(ReadLockGuard(g)),
-            None => None,
+        self.stripe(id).rwlock.try_read().map(ReadLockGuard)
+    }
+
+    /// The stripes must not grow while the guard is in use.
+    /// The caller validates the root for that.
+    pub fn optimistic_read(&self, id: u64) -> OptimisticReadGuard<'_> {
+        let stripe = self.stripe(id);
+        OptimisticReadGuard {
+            stripe,
+            start_seq: stripe.seq.load(Ordering::SeqCst),
         }
     }
 
     pub fn selective_lock(&self, id: u64) -> SelectiveLockGuard<'_> {
-        let b = (id as usize) % self.n;
-        let g1 = self.rwlocks[b].read();
-        let g2 = self.mutexes[b].lock();
-        SelectiveLockGuard(g1, g2)
+        self.acquire(
+            id,
+            |x| {
+                let g1 = x.rwlock.try_read()?;
+                let g2 = x.mutex.try_lock()?;
+                Some(SelectiveLockGuard::new(x, g1, g2))
+            },
+            |x| {
+                let g1 = x.rwlock.read();
+                let g2 = x.mutex.lock();
+                SelectiveLockGuard::new(x, g1, g2)
+            },
+        )
     }
 
+    #[allow(unused)]
     pub fn try_selective_lock(&self, id: u64) -> Option<SelectiveLockGuard<'_>> {
-        let b = (id as usize) % self.n;
-        let g1 = match self.rwlocks[b].try_read() {
-            Some(g) => g,
-            None => return None,
-        };
-        let g2 = match self.mutexes[b].try_lock() {
-            Some(g) => g,
-            None => return None,
-        };
-        Some(SelectiveLockGuard(g1, g2))
+        let stripe = self.stripe(id);
+        let g1 = stripe.rwlock.try_read()?;
+        let g2 = stripe.mutex.try_lock()?;
+        Some(SelectiveLockGuard::new(stripe, g1, g2))
     }
 
+    #[allow(unused)]
     pub fn exclusive_lock(&self, id: u64) -> ExclusiveLockGuard<'_> {
-        let b = (id as usize) % self.n;
-        ExclusiveLockGuard(self.rwlocks[b].write())
+        self.acquire(
+            id,
+            |x| x.rwlock.try_write().map(ExclusiveLockGuard),
+            |x| ExclusiveLockGuard(x.rwlock.write()),
+        )
     }
 
+    #[allow(unused)]
     pub fn try_exclusive_lock(&self, id: u64) -> Option<ExclusiveLockGuard<'_>> {
-        let b = (id as usize) % self.n;
-        match self.rwlocks[b].try_write() {
-            Some(g) => Some(ExclusiveLockGuard(g)),
-            None => None,
-        }
+        self.stripe(id).rwlock.try_write().map(ExclusiveLockGuard)
     }
 }
 
 #[cfg(test)]
+#[allow(unused_variables, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -120,4 +305,89 @@ mod tests {
         let g2 = lock.try_exclusive_lock(0);
         assert_eq!(g2.is_none(), true);
     }
+
+    #[test]
+    fn test_lock_grow() {
+        let root = RwLock::new(Root {
+            base_level: 1,
+            next_split_primary_page_id: 0,
+        });
+        let lock = StripeLock::new(4);
+        {
+            let _g1 = lock.exclusive_lock(0);
+            assert!(lock.try_read_lock(4).is_none());
+        }
+
+        lock.grow(10, &root.write());
+        assert_eq!(lock.stat().n_stripes, 16);
+        for id in 0..32 {
+            let _g = lock.exclusive_lock(id);
+        }
+        let _g1 = lock.exclusive_lock(0);
+        let g2 = lock.try_read_lock(4);
+        assert!(g2.is_some());
+    }
+
+    #[test]
+    fn test_lock_collision() {
+        let lock = StripeLock::new(4);
+        let g1 = lock.exclusive_lock(0);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let _g2 = lock.read_lock(4);
+            });
+            while lock.stat().n_contended == 0 {
+                std::thread::yield_now();
+            }
+            drop(g1);
+        });
+
+        let stat = lock.stat();
+        assert_eq!(stat.n_acquired, 2);
+        assert_eq!(stat.n_contended, 1);
+        assert_eq!(stat.n_collisions, 1);
+    }
+
+    #[test]
+    fn test_lock_seq() {
+        let lock = StripeLock::new(4);
+        let g1 = lock.optimistic_read(0);
+        {
+            let g2 = lock.selective_lock(4);
+            let _seq = g2.write_seq();
+            assert!(!lock.optimistic_read(0).validate());
+        }
+        assert!(!g1.validate());
+        assert!(lock.optimistic_read(0).validate());
+        assert!(lock.optimistic_read(1).validate());
+        assert_eq!(lock.stat().n_acquired, 1);
+    }
+
+    #[test]
+    fn test_optimistic_read_wait() {
+        let lock = StripeLock::new(4);
+        // A holder not modifying the stripe isn't waited for.
+        {
+            let _g1 = lock.selective_lock(0);
+            lock.optimistic_read(4).wait();
+        }
+
+        let released = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            let g1 = lock.selective_lock(0);
+            let _seq = g1.write_seq();
+            let h = s.spawn(|| {
+                let g2 = lock.optimistic_read(4);
+                g2.wait();
+                assert!(released.load(Ordering::SeqCst));
+                assert!(!g2.validate());
+                assert!(lock.optimistic_read(4).validate());
+            });
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(!h.is_finished());
+            released.store(true, Ordering::SeqCst);
+        });
+        // Waiting isn't counted as an acquisition.
+        assert_eq!(lock.stat().n_acquired, 2);
+    }
 }
@@ -0,0 +1,41 @@
+use super::*;
+
+/// The in-memory state recorded on flush and shutdown.
+/// A clean checkpoint lets the database open without scanning the table.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// False once the database is modified after the checkpoint.
+    /// If it is false on open, the last shutdown wasn't clean.
+    pub clean: bool,
+    pub n_items: u64,
+    pub base_level: u8,
+    pub next_split_primary_page_id: u64,
+    pub next_overflow_id: u64,
+    /// The overflow pages below `next_overflow_id` which can be reused.
+    pub free_overflow_ids: Vec<u64>,
+    pub damaged_buckets: Vec<u64>,
+}
+
+impl Checkpoint {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join("checkpoint")
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let data = rkyv::to_bytes::<rkyv::rancor::Error>(self)?;
+        write_file(&Self::path(dir), &data)
+    }
+
+    /// Returns `None` if there is no valid checkpoint.
+    /// A broken checkpoint is not an error because it is only a hint.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let data = match read_file(&Self::path(dir)) {
+            Ok(Some(data)) => data,
+            Ok(None) | Err(Error::InvalidMeta(_) | Error::UnsupportedVersion(_)) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(rkyv::from_bytes::<Self, rkyv::rancor::Error>(&data).ok())
+    }
+}
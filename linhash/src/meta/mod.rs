@@ -0,0 +1,85 @@
+use super::*;
+
+use std::io::Write;
+
+mod superblock;
+pub use superblock::{HashMode, Superblock};
+
+mod checkpoint;
+pub use checkpoint::Checkpoint;
+
+const MAGIC: u32 = 0x4c6e4d74; // LnMt
+const HEADER_LEN: usize = 16;
+
+/// The on-disk format version.
+/// Bump this whenever the layout of any file in the directory changes.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Write a small metadata file atomically.
+/// The content is written to a temporary file and then renamed over the old one.
+fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    let crc = crc32fast::hash(data);
+    let data_len = data.len() as u32;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + data.len());
+    out.extend_from_slice(&MAGIC.to_le_bytes()); // 4
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes()); // 4
+    out.extend_from_slice(&crc.to_le_bytes()); // 4
+    out.extend_from_slice(&data_len.to_le_bytes()); // 4
+    out.extend_from_slice(data);
+
+    let tmp_path = path.with_extension("tmp");
+    let mut f = std::fs::File::create(&tmp_path)?;
+    f.write_all(&out)?;
+    f.sync_all()?;
+    drop(f);
+
+    std::fs::rename(&tmp_path, path)?;
+    // Persist the rename.
+    if let Some(dir) = path.parent() {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Read a metadata file written by `write_file`.
+/// Returns `None` if the file doesn't exist.
+fn read_file(path: &Path) -> Result<Option<rkyv::util::AlignedVec>> {
+    let buf = match std::fs::read(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let invalid = || Error::InvalidMeta(path.to_owned());
+
+    if buf.len() < HEADER_LEN {
+        return Err(invalid());
+    }
+
+    let stored_magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+    if stored_magic != MAGIC {
+        return Err(invalid());
+    }
+
+    let version = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    let stored_crc = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+    let data_len = u32::from_le_bytes(buf[12..16].try_into().unwrap()) as usize;
+    let Some(data) = buf.get(HEADER_LEN..HEADER_LEN + data_len) else {
+        return Err(invalid());
+    };
+
+    if crc32fast::hash(data) != stored_crc {
+        return Err(invalid());
+    }
+
+    let mut out = rkyv::util::AlignedVec::with_capacity(data_len);
+    out.extend_from_slice(data);
+
+    Ok(Some(out))
+}
@@ -0,0 +1,129 @@
+use super::*;
+
+/// How a key is mapped to the hash value which decides its bucket.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum HashMode {
+    /// xxh3 of the whole key. (`hash` feature enabled)
+    Xxh3,
+    /// The first 64 bits of the key as is. (`hash` feature disabled)
+    Raw,
+}
+
+impl HashMode {
+    /// The hash mode this build is compiled with.
+    pub fn current() -> Self {
+        if cfg!(feature = "hash") {
+            HashMode::Xxh3
+        } else {
+            HashMode::Raw
+        }
+    }
+}
+
+/// The immutable properties of the database written once at creation time.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone, PartialEq)]
+pub struct Superblock {
+    pub ksize: u64,
+    pub vsize: u64,
+    pub pagesize: u64,
+    pub hash_mode: HashMode,
+    /// The number of buckets the table was created with.
+    /// Merges never shrink the table below this.
+    pub min_buckets: u64,
+}
+
+impl Superblock {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join("superblock")
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let data = rkyv::to_bytes::<rkyv::rancor::Error>(self)?;
+        write_file(&Self::path(dir), &data)
+    }
+
+    /// Returns `None` if the database isn't created yet.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(dir);
+        let Some(data) = read_file(&path)? else {
+            return Ok(None);
+        };
+        let sb = rkyv::from_bytes::<Self, rkyv::rancor::Error>(&data)
+            .map_err(|_| Error::InvalidMeta(path))?;
+        Ok(Some(sb))
+    }
+
+    /// Check that the database can be opened with the given config.
+    pub fn validate(&self, config: &LinHashConfig) -> Result<()> {
+        let fields = [
+            ("ksize", self.ksize, config.ksize as u64),
+            ("vsize", self.vsize, config.vsize as u64),
+            ("pagesize", self.pagesize, config.pagesize as u64),
+        ];
+        for (field, found, expected) in fields {
+            if found != expected {
+                return Err(Error::ConfigMismatch {
+                    field,
+                    expected,
+                    found,
+                });
+            }
+        }
+
+        let current = HashMode::current();
+        if self.hash_mode != current {
+            return Err(Error::HashModeMismatch {
+                expected: current,
+                found: self.hash_mode,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_superblock_write_read() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Superblock::read(dir.path()).unwrap(), None);
+
+        let sb = Superblock {
+            ksize: 8,
+            vsize: 16,
+            pagesize: 4096,
+            hash_mode: HashMode::current(),
+            min_buckets: 2,
+        };
+        sb.write(dir.path()).unwrap();
+
+        assert_eq!(Superblock::read(dir.path()).unwrap(), Some(sb));
+    }
+
+    #[test]
+    fn test_superblock_crc_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let sb = Superblock {
+            ksize: 8,
+            vsize: 16,
+            pagesize: 4096,
+            hash_mode: HashMode::current(),
+            min_buckets: 2,
+        };
+        sb.write(dir.path()).unwrap();
+
+        let path = Superblock::path(dir.path());
+        let mut buf = std::fs::read(&path).unwrap();
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+        std::fs::write(&path, buf).unwrap();
+
+        assert!(matches!(
+            Superblock::read(dir.path()),
+            Err(Error::InvalidMeta(_))
+        ));
+    }
+}
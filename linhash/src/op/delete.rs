@@ -3,52 +3,101 @@ use super::*;
 pub struct Delete<'a> {
     pub db: &'a LinHashCore,
     pub chain_id: PageChainId,
-    #[allow(unused)]
     pub root: RwLockReadGuard<'a, Root>,
-    // Why exclusive lock?
-    // If GET and INSERT and DELETE are allowed concurrently, GET may read a k-v pair twice.
-    // This is almost impossible in practice but possible if GET process stalls for a very long time.
-    #[allow(unused)]
-    pub lock: lock::ExclusiveLockGuard<'a>,
+    // If GET and INSERT and DELETE are allowed concurrently, GET may miss a pair
+    // moved by DELETE and INSERT behind the pages it has read.
+    // DELETE bumps the sequence number of the stripe so that such GET retries.
+    pub lock: lock::SelectiveLockGuard<'a>,
 }
 
 impl Delete<'_> {
     pub fn exec(self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.db.mark_dirty(&self.root)?;
+
+        let _seq = self.lock.write_seq();
+        let (old, compactable) = self.delete(key)?;
+        // Counted under the root lock so that a checkpoint sees the count consistent with the pages.
+        if old.is_some() {
+            self.db.n_items.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        if compactable {
+            self.compact()?;
+        }
+
+        Ok(old)
+    }
+
+    /// Also returns true if compacting the chain surely saves a page.
+    /// It is decided from the pages read until the key
+    /// so that a delete which can't save a page doesn't read the rest of the chain.
+    fn delete(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, bool)> {
         let chain_id = self.chain_id;
+        let max_kv_per_page = self.db.max_kv_per_page as usize;
 
         let mut cur_page = (
             PageId::Primary(chain_id.primary_page_id),
-            self.db
-                .primary_pages
-                .read_page(chain_id.primary_page_id)?
-                .unwrap(),
+            self.db.primary_pages.read_page(chain_id.primary_page_id)?,
         );
 
         if cur_page.1.locallevel != Some(chain_id.locallevel) {
             return Err(Error::LocalLevelMismatch);
         }
 
+        // The pages read so far and the pairs in them.
+        let mut n_pages = 0;
+        let mut n_pairs = 0;
+
         loop {
+            n_pages += 1;
+            n_pairs += cur_page.1.kv_pairs.len();
+
             if cur_page.1.contains(key) {
                 let removed = cur_page.1.kv_pairs.remove(key);
                 match cur_page.0 {
                     PageId::Primary(b) => self.db.primary_pages.write_page(b, &cur_page.1)?,
                     PageId::Overflow(id) => self.db.overflow_pages.write_page(id, &cur_page.1)?,
                 }
+                n_pairs -= 1;
 
-                return Ok(removed);
+                // The rest of the chain needs no more pages than it has
+                // so a page is saved if the pages read have room for a page worth of pairs.
+                let in_chain = n_pages > 1 || cur_page.1.overflow_id.is_some();
+                let room = n_pages * max_kv_per_page - n_pairs;
+
+                return Ok((removed, in_chain && room >= max_kv_per_page));
             }
 
             if let Some(overflow_id) = cur_page.1.overflow_id {
                 cur_page = (
                     PageId::Overflow(overflow_id),
-                    self.db.overflow_pages.read_page(overflow_id)?.unwrap(),
+                    self.db.overflow_pages.read_page(overflow_id)?,
                 );
             } else {
                 break;
             }
         }
 
-        Ok(None)
+        Ok((None, false))
+    }
+
+    /// Rewrite the page chain into the minimal number of pages and release the rest.
+    fn compact(&self) -> Result<()> {
+        let primary_page_id = self.chain_id.primary_page_id;
+
+        let (kv_pairs, old_overflow_ids) = self.db.collect_chain(primary_page_id)?;
+
+        let n_pages = kv_pairs.len().div_ceil(self.db.max_kv_per_page as usize);
+        if n_pages.max(1) > old_overflow_ids.len() {
+            return Ok(());
+        }
+
+        // The old pages are released after the new chain is committed
+        // so they are never reused for the new chain.
+        self.db
+            .write_chain(primary_page_id, self.chain_id.locallevel, kv_pairs)?;
+        self.db.release_overflow_pages(old_overflow_ids);
+
+        Ok(())
     }
 }
@@ -0,0 +1,30 @@
+use super::*;
+
+use std::ops::ControlFlow;
+
+/// Visit the pairs group by group with the slices borrowed from the pages.
+/// A group's pages are read under the locks like LIST and visited after the locks are released.
+pub struct ForEach<'a> {
+    pub db: &'a LinHashCore,
+}
+
+impl ForEach<'_> {
+    /// Stops when the visitor breaks and returns its value.
+    pub fn exec<B>(
+        self,
+        mut f: impl FnMut(&[u8], &[u8]) -> ControlFlow<B>,
+    ) -> Result<ControlFlow<B>> {
+        let level = self.db.root.read().base_level;
+
+        for group in 0..1 << level {
+            let pages = list::read_group_pages(self.db, level, group)?;
+            for (k, v) in pages.kv_pairs(self.db) {
+                if let ControlFlow::Break(b) = f(k, v) {
+                    return Ok(ControlFlow::Break(b));
+                }
+            }
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+}
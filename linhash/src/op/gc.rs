@@ -1,20 +1,49 @@
 use super::*;
 
+/// Reclaim the overflow pages freed by operations so that they can be reused.
 pub struct GC<'a> {
     pub db: &'a LinHashCore,
-    pub root: Root,
+    // No operation is in flight while the root is write-locked.
+    // So once the pending pages are taken, nobody but the optimistic readers is reading them.
+    // The lock is released before the slow part so that the operations are blocked only briefly.
+    pub root: RwLockWriteGuard<'a, Root>,
 }
 
 impl GC<'_> {
     pub fn exec(self) -> Result<()> {
-        let range = util::TraverseOverflowPages {
-            db: self.db,
-            root: self.root,
+        let GC { db, root } = self;
+
+        let ranges = db.free_list.lock().take_pending();
+        if ranges.is_empty() {
+            return Ok(());
         }
-        .exec()?;
 
-        self.db.overflow_pages.free_page_range(0, range.start)?;
+        // The optimistic readers which may still be reading the pages fail to validate.
+        drop(db.root_seq.begin_write(&root));
+        drop(root);
+
+        if let Err(e) = punch(db, &ranges) {
+            // Retry next time.
+            db.free_list.lock().abort_reclaim(ranges);
+            return Err(e);
+        }
+
+        let n: u64 = ranges.iter().map(|x| x.end - x.start).sum();
+        db.free_list.lock().reclaim(ranges);
+        db.n_reclaimed_overflow_pages.fetch_add(n, Ordering::SeqCst);
 
         Ok(())
     }
 }
+
+fn punch(db: &LinHashCore, ranges: &[Range<u64>]) -> Result<()> {
+    // The primary pages which referenced the freed pages must be persisted before reuse.
+    // Otherwise a crash could bring back a reference to a page which is overwritten.
+    db.primary_pages.flush()?;
+
+    for range in ranges {
+        db.overflow_pages.free_page_range(range.start, range.end)?;
+    }
+
+    Ok(())
+}
@@ -3,23 +3,91 @@ use super::*;
 pub struct Get<'a> {
     pub db: &'a LinHashCore,
     pub chain_id: PageChainId,
-    #[allow(unused)]
-    pub root: RwLockReadGuard<'a, Root>,
-    #[allow(unused)]
-    pub lock: lock::ReadLockGuard<'a>,
+    // GET doesn't lock anything. It reads the pages optimistically and validates them afterward.
+    // The freed overflow pages are reused only after GC, which changes the root sequence number.
+    pub root: RootSeqReadGuard<'a>,
+    pub lock: lock::OptimisticReadGuard<'a>,
 }
 
 impl Get<'_> {
-    pub fn exec(self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    /// Returns the page which has the key.
+    pub fn exec(self, key: &[u8]) -> Result<Option<PageRef>> {
+        let res = self.get(key);
+
+        // Anything read may be inconsistent including an error
+        // if the root or the page layout changed meanwhile.
+        if !self.root.validate() {
+            return Err(Error::VersionMismatch);
+        }
+
+        let (page, hops) = res.map_err(|e| self.check_error(e))?;
+        match page {
+            Some(page) => {
+                self.db.stat.push(OpEvent::GetHit(hops));
+                Ok(Some(page))
+            }
+            None => {
+                // A hit is valid regardless because the pair was in the page when read.
+                // A miss isn't if a concurrent delete moved the pair behind the pages read.
+                if !self.lock.validate() {
+                    return Err(self.wait_for_writer());
+                }
+                self.db.stat.push(OpEvent::GetMiss(hops));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Look up the keys in the chain reading each page at most once.
+    pub fn exec_many(self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let res = self.get_many(keys);
+
+        if !self.root.validate() {
+            return Err(Error::VersionMismatch);
+        }
+
+        let (vals, hops) = res.map_err(|e| self.check_error(e))?;
+        if vals.iter().any(|v| v.is_none()) && !self.lock.validate() {
+            return Err(self.wait_for_writer());
+        }
+        for v in &vals {
+            match v {
+                Some(_) => self.db.stat.push(OpEvent::GetHit(hops)),
+                None => self.db.stat.push(OpEvent::GetMiss(hops)),
+            }
+        }
+
+        Ok(vals)
+    }
+
+    // An error, such as a checksum mismatch, may come from a page rewritten while it was read.
+    fn check_error(&self, e: Error) -> Error {
+        if self.lock.validate() {
+            e
+        } else {
+            self.wait_for_writer()
+        }
+    }
+
+    // The writer may hold the stripe for long, including a compaction with its sync,
+    // so the retry waits for it instead of reading the pages again and again.
+    fn wait_for_writer(&self) -> Error {
+        self.lock.wait();
+        Error::VersionMismatch
+    }
+
+    /// Also returns the number of pages read.
+    fn get_many(&self, keys: &[&[u8]]) -> Result<(Vec<Option<Vec<u8>>>, u64)> {
         let chain_id = self.chain_id;
 
+        let mut vals = vec![None; keys.len()];
+        let mut n_found = 0;
         let mut hops = 0;
 
         let mut page = self
             .db
             .primary_pages
-            .read_page_ref(chain_id.primary_page_id)?
-            .unwrap();
+            .read_page_ref(chain_id.primary_page_id)?;
         hops += 1;
 
         if page.locallevel() != Some(chain_id.locallevel) {
@@ -27,20 +95,58 @@ impl Get<'_> {
         }
 
         loop {
-            if let Some(v) = page.get_value(key) {
-                self.db.stat.lock().push(OpEvent::GetHit(hops));
-                return Ok(Some(v.to_owned()));
+            for (key, val) in keys.iter().zip(&mut vals) {
+                if val.is_some() {
+                    continue;
+                }
+                if let Some(v) = page.get_value(key) {
+                    *val = Some(v.to_owned());
+                    n_found += 1;
+                }
+            }
+            if n_found == keys.len() {
+                break;
             }
 
             match page.overflow_id() {
                 Some(id) => {
-                    page = self.db.overflow_pages.read_page_ref(id)?.unwrap();
+                    page = self.db.overflow_pages.read_page_ref(id)?;
                     hops += 1;
                 }
-                None => {
-                    self.db.stat.lock().push(OpEvent::GetMiss(hops));
-                    return Ok(None);
+                None => break,
+            }
+        }
+
+        Ok((vals, hops))
+    }
+
+    /// Also returns the number of pages read.
+    fn get(&self, key: &[u8]) -> Result<(Option<PageRef>, u64)> {
+        let chain_id = self.chain_id;
+
+        let mut hops = 0;
+
+        let mut page = self
+            .db
+            .primary_pages
+            .read_page_ref(chain_id.primary_page_id)?;
+        hops += 1;
+
+        if page.locallevel() != Some(chain_id.locallevel) {
+            return Err(Error::LocalLevelMismatch);
+        }
+
+        loop {
+            if page.get_value(key).is_some() {
+                return Ok((Some(page), hops));
+            }
+
+            match page.overflow_id() {
+                Some(id) => {
+                    page = self.db.overflow_pages.read_page_ref(id)?;
+                    hops += 1;
                 }
+                None => return Ok((None, hops)),
             }
         }
     }
@@ -3,24 +3,32 @@ use super::*;
 pub struct Insert<'a> {
     pub db: &'a LinHashCore,
     pub chain_id: PageChainId,
-    #[allow(unused)]
     pub root: RwLockReadGuard<'a, Root>,
     #[allow(unused)]
     pub lock: lock::SelectiveLockGuard<'a>,
 }
 
 impl Insert<'_> {
-    pub fn exec(self, key: Vec<u8>, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+    pub fn exec(self, key: Vec<u8>, value: Vec<u8>) -> Result<(Option<Vec<u8>>, ResizeEvent)> {
+        self.db.mark_dirty(&self.root)?;
+
+        let (old, evt) = self.insert(key, value)?;
+        // Counted under the root lock so that a checkpoint sees the count consistent with the pages.
+        if old.is_none() {
+            self.db.n_items.fetch_add(1, Ordering::SeqCst);
+        }
+
+        Ok((old, evt))
+    }
+
+    fn insert(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(Option<Vec<u8>>, ResizeEvent)> {
         let chain_id = self.chain_id;
 
         let mut pages = VecDeque::new();
 
         let next_page = (
             PageId::Primary(chain_id.primary_page_id),
-            self.db
-                .primary_pages
-                .read_page(chain_id.primary_page_id)?
-                .unwrap(),
+            self.db.primary_pages.read_page(chain_id.primary_page_id)?,
         );
 
         if next_page.1.locallevel != Some(chain_id.locallevel) {
@@ -38,13 +46,17 @@ impl Insert<'_> {
                     PageId::Primary(b) => self.db.primary_pages.write_page(b, &cur_page.1)?,
                     PageId::Overflow(id) => self.db.overflow_pages.write_page(id, &cur_page.1)?,
                 }
-                return Ok(old);
+                let evt = ResizeEvent::Insert {
+                    chain_len: pages.len(),
+                    overflow_allocated: false,
+                };
+                return Ok((old, evt));
             }
 
             if let Some(overflow_id) = cur_page.1.overflow_id {
                 let next_page = (
                     PageId::Overflow(overflow_id),
-                    self.db.overflow_pages.read_page(overflow_id)?.unwrap(),
+                    self.db.overflow_pages.read_page(overflow_id)?,
                 );
                 pages.push_back(next_page);
             } else {
@@ -59,14 +71,19 @@ impl Insert<'_> {
                     PageId::Primary(b) => self.db.primary_pages.write_page(b, &cur_page.1)?,
                     PageId::Overflow(id) => self.db.overflow_pages.write_page(id, &cur_page.1)?,
                 }
-                return Ok(None);
+                let evt = ResizeEvent::Insert {
+                    chain_len: pages.len(),
+                    overflow_allocated: false,
+                };
+                return Ok((None, evt));
             }
         }
 
+        let chain_len = pages.len() + 1;
         let tail_page = pages.back_mut().unwrap();
 
         // If not, allocate a new overflow page.
-        let new_overflow_id = self.db.next_overflow_id.fetch_add(1, Ordering::SeqCst);
+        let new_overflow_id = self.db.alloc_overflow_id();
         let mut new_page = Page::new();
         new_page.insert(key, value);
         self.db
@@ -87,6 +104,10 @@ impl Insert<'_> {
             }
         }
 
-        Ok(None)
+        let evt = ResizeEvent::Insert {
+            chain_len,
+            overflow_allocated: true,
+        };
+        Ok((None, evt))
     }
 }
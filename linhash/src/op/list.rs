@@ -2,66 +2,167 @@ use super::*;
 
 use genawaiter::sync::{Co, Gen};
 
+/// List the pairs group by group without blocking the writers for the whole scan.
+///
+/// A group is the set of the keys whose hash has the same lower bits at the level the scan started at.
+/// Splits and merges only move keys between the buckets of the same group,
+/// so reading a group at once under the root read lock never misses or duplicates a key.
 pub struct List<'a> {
     pub db: &'a LinHashCore,
-    #[allow(unused)]
-    pub root: RwLockWriteGuard<'a, Root>,
+    pub level: u8,
+    pub groups: Range<u64>,
 }
 
-impl List<'_> {
-    pub fn exec(self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> {
-        Gen::new(|co: Co<(Vec<u8>, Vec<u8>)>| async move {
-            // We have to hold the root lock in the generator
-            // or the lock will be dropped when the iterator is returned.
-            let _root = self.root;
+impl<'a> List<'a> {
+    pub fn exec(self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a {
+        Gen::new(|co: Co<Result<(Vec<u8>, Vec<u8>)>>| async move {
+            let level = self.level;
 
-            let mut page_id = 0;
-
-            loop {
-                // Stop if valid primary page does not exist.
-                let Ok(Some(page)) = self.db.primary_pages.read_page_ref(page_id) else {
-                    return;
+            for group in self.groups {
+                // No lock is held while yielding the pairs.
+                let kv_pairs = match read_group(self.db, level, group) {
+                    Ok(kv_pairs) => kv_pairs,
+                    Err(e) => {
+                        co.yield_(Err(e)).await;
+                        return;
+                    }
                 };
 
-                let it = ListOnce { db: self.db, page }.exec();
-
-                for (k, v) in it {
-                    co.yield_((k, v)).await;
+                for kv in kv_pairs {
+                    co.yield_(Ok(kv)).await;
                 }
-
-                page_id += 1;
             }
         })
         .into_iter()
     }
 }
 
-struct ListOnce<'a> {
-    pub db: &'a LinHashCore,
-    pub page: PageRef,
+/// A disjoint part of the table to scan. See `LinHash::partitions`.
+#[derive(Clone)]
+pub struct Partition<'a> {
+    db: &'a LinHashCore,
+    level: u8,
+    groups: Range<u64>,
 }
 
-impl ListOnce<'_> {
-    pub fn exec(self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> {
-        Gen::new(move |co: Co<(Vec<u8>, Vec<u8>)>| async move {
-            let mut cur_page = self.page;
+impl<'a> Partition<'a> {
+    /// Split the groups at the level into `k` partitions.
+    pub(crate) fn split(db: &'a LinHashCore, level: u8, k: u64) -> Vec<Self> {
+        let n_groups = 1u128 << level;
+        // In u128 not to overflow.
+        let bound = |i: u64| (i as u128 * n_groups / k as u128) as u64;
+        (0..k)
+            .map(|i| Partition {
+                db,
+                level,
+                groups: bound(i)..bound(i + 1),
+            })
+            .collect()
+    }
 
-            loop {
-                for (k, v) in cur_page.kv_pairs() {
-                    co.yield_((k.to_vec(), v.to_vec())).await
-                }
+    /// The iterator stops after yielding an error.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a {
+        List {
+            db: self.db,
+            level: self.level,
+            groups: self.groups.clone(),
+        }
+        .exec()
+    }
+}
 
-                match cur_page.overflow_id() {
-                    Some(id) => {
-                        let Ok(Some(next_page)) = self.db.overflow_pages.read_page_ref(id) else {
-                            return;
-                        };
-                        cur_page = next_page;
-                    }
-                    None => return,
-                }
+/// Read the pairs of the group at the level.
+pub fn read_group(db: &LinHashCore, level: u8, group: u64) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let pages = read_group_pages(db, level, group)?;
+    Ok(pages
+        .kv_pairs(db)
+        .map(|(k, v)| (k.to_vec(), v.to_vec()))
+        .collect())
+}
+
+/// Read the pages which have the pairs of the group at the level.
+pub fn read_group_pages(db: &LinHashCore, level: u8, group: u64) -> Result<GroupPages> {
+    loop {
+        let res = ListGroup {
+            db,
+            root: db.root.read(),
+            level,
+            group,
+        }
+        .exec();
+
+        match res {
+            // A split is committed but the root isn't advanced yet.
+            Err(Error::LocalLevelMismatch) => continue,
+            res => return res,
+        }
+    }
+}
+
+/// The pages may have the pairs of other groups.
+pub struct GroupPages {
+    level: u8,
+    group: u64,
+    pages: Vec<PageRef>,
+}
+
+impl GroupPages {
+    /// The pairs of the group borrowed from the pages.
+    pub fn kv_pairs<'a>(
+        &'a self,
+        db: &'a LinHashCore,
+    ) -> impl Iterator<Item = (&'a [u8], &'a [u8])> {
+        let mask = (1 << self.level) - 1;
+        self.pages
+            .iter()
+            .flat_map(|page| page.kv_pairs())
+            .filter(move |(k, _)| db.calc_hash(k) & mask == self.group)
+    }
+}
+
+struct ListGroup<'a> {
+    db: &'a LinHashCore,
+    // Splits and merges are blocked while reading the group.
+    root: RwLockReadGuard<'a, Root>,
+    level: u8,
+    group: u64,
+}
+
+impl ListGroup<'_> {
+    fn exec(self) -> Result<GroupPages> {
+        // The buckets which may have the keys of the group.
+        // If the table shrank below the level, a bucket has the keys of several groups.
+        let first = self.root.calc_page_chain_id(self.group).primary_page_id;
+        let buckets = std::iter::once(first).chain(
+            (self.group..self.root.calc_n_pages())
+                .step_by(1 << self.level)
+                .filter(|&id| id != first),
+        );
+
+        let mut pages = Vec::new();
+        for primary_page_id in buckets {
+            let chain_id = self.root.calc_page_chain_id(primary_page_id);
+            assert_eq!(chain_id.primary_page_id, primary_page_id);
+            let _lock = self.db.locks.selective_lock(primary_page_id);
+
+            let page = self.db.primary_pages.read_page_ref(primary_page_id)?;
+            if page.locallevel() != Some(chain_id.locallevel) {
+                return Err(Error::LocalLevelMismatch);
             }
+
+            let mut overflow_id = page.overflow_id();
+            pages.push(page);
+            while let Some(id) = overflow_id {
+                let page = self.db.overflow_pages.read_page_ref(id)?;
+                overflow_id = page.overflow_id();
+                pages.push(page);
+            }
+        }
+
+        Ok(GroupPages {
+            level: self.level,
+            group: self.group,
+            pages,
         })
-        .into_iter()
     }
 }
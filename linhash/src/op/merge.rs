@@ -0,0 +1,98 @@
+use super::*;
+
+pub struct Merge<'a> {
+    pub db: &'a LinHashCore,
+    // Merge is rare so it blocks all the operations to keep it simple.
+    pub root: RwLockWriteGuard<'a, Root>,
+}
+
+impl Merge<'_> {
+    /// Merge the last primary page back into its buddy and retreat the split pointer.
+    pub fn exec(mut self) -> Result<()> {
+        self.db.mark_dirty(&self.root)?;
+
+        // The pages of both buckets are rewritten and the last page is dropped under optimistic readers.
+        let seq = self.db.root_seq.begin_write(&self.root);
+
+        let mut new_root = *self.root;
+        new_root.retreat_split_pointer();
+
+        // The reverse of the split which created the last page.
+        let buddy_id = new_root.next_split_primary_page_id;
+        let merged_id = buddy_id + (1 << new_root.base_level);
+        assert_eq!(merged_id + 1, self.root.calc_n_pages());
+
+        let (mut kv_pairs, mut old_overflow_ids) = self.db.collect_chain(buddy_id)?;
+        let (merged_kv_pairs, merged_overflow_ids) = self.db.collect_chain(merged_id)?;
+        kv_pairs.extend(merged_kv_pairs);
+        old_overflow_ids.extend(merged_overflow_ids);
+
+        self.db
+            .write_chain(buddy_id, new_root.base_level, kv_pairs)?;
+        // The buddy page must be persisted before the last page is dropped.
+        // Until the last page is dropped, recovery sees the pair as an uncommitted split
+        // and discards the last page.
+        self.db.primary_pages.flush()?;
+
+        self.db.primary_pages.truncate(merged_id)?;
+        // The truncation must be persisted before the next merge.
+        // Otherwise recovery could find more than one merged page at the tail.
+        self.db.primary_pages.flush()?;
+
+        self.db.release_overflow_pages(old_overflow_ids);
+
+        *self.root = new_root;
+        seq.publish(&new_root);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vec(i: u64) -> Vec<u8> {
+        i.to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_interrupted_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = LinHashConfig::builder().ksize(8).vsize(8).build();
+
+        let n = 5000;
+        let db = LinHash::open(dir.path(), config.clone()).unwrap();
+        for i in 0..n {
+            db.insert(vec(i), vec(i)).unwrap();
+        }
+        drop(db);
+
+        // Crash after the buddy page is written but before the last page is dropped.
+        let merged_id = {
+            let (db, _) = LinHashCore::open(dir.path(), &config).unwrap();
+            let mut root = *db.root.read();
+            let merged_id = root.calc_n_pages() - 1;
+            root.retreat_split_pointer();
+            let buddy_id = root.next_split_primary_page_id;
+
+            let (mut kv_pairs, _) = db.collect_chain(buddy_id).unwrap();
+            kv_pairs.extend(db.collect_chain(merged_id).unwrap().0);
+            db.write_chain(buddy_id, root.base_level, kv_pairs).unwrap();
+            db.primary_pages.flush().unwrap();
+
+            merged_id
+        };
+        std::fs::remove_file(meta::Checkpoint::path(dir.path())).unwrap();
+
+        let db = LinHash::open(dir.path(), config).unwrap();
+        let report = db.recovery_report();
+        assert_eq!(report.discarded_split_page, Some(merged_id));
+        assert_eq!(report.n_buckets, merged_id);
+        assert!(report.damaged_buckets.is_empty());
+        assert_eq!(db.len(), n);
+        for i in 0..n {
+            assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+        }
+    }
+}
@@ -12,8 +12,17 @@ pub use insert::Insert;
 mod delete;
 pub use delete::Delete;
 
+mod merge;
+pub use merge::Merge;
+
 mod list;
-pub use list::List;
+pub use list::{List, Partition};
+
+mod for_each;
+pub use for_each::ForEach;
+
+mod scan;
+pub use scan::{Scan, ScanBatch, ScanCursor};
 
 mod gc;
 pub use gc::GC;
@@ -0,0 +1,186 @@
+use super::*;
+
+/// Where a paginated scan resumes.
+///
+/// The scan goes through the same groups as LIST in order and the pairs of a group in key order,
+/// so a pair present during the whole scan is returned exactly once even if the table is resized in between.
+/// The cursor can be persisted with `to_bytes` and restored with `from_bytes`.
+///
+/// A page within a group reads and sorts the whole group again,
+/// so paging through a group of `n` pairs with `limit` costs O(n² / limit · log n).
+/// A group is about a bucket at the level the scan started at, so keep `limit` above the pairs of a few pages.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanCursor {
+    // The groups are fixed at the level the scan started at. None before the scan starts.
+    level: Option<u8>,
+    group: u64,
+    // The last key returned in the group.
+    last_key: Option<Vec<u8>>,
+}
+
+impl ScanCursor {
+    /// The cursor to start a scan from the beginning.
+    pub fn start() -> Self {
+        Self::default()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let Some(level) = self.level else {
+            return Vec::new();
+        };
+        let mut buf = vec![level];
+        buf.extend(self.group.to_le_bytes());
+        if let Some(key) = &self.last_key {
+            buf.push(1);
+            buf.extend(key);
+        }
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid scan cursor of {} bytes", buf.len()),
+            )
+        };
+
+        let Some((&level, rest)) = buf.split_first() else {
+            return Ok(Self::start());
+        };
+        let (group, rest) = rest.split_first_chunk::<8>().ok_or_else(invalid)?;
+        let group = u64::from_le_bytes(*group);
+        if level >= 64 || group >= 1 << level {
+            return Err(invalid().into());
+        }
+        let last_key = match rest.split_first() {
+            None => None,
+            Some((1, key)) => Some(key.to_vec()),
+            Some(_) => return Err(invalid().into()),
+        };
+        Ok(Self {
+            level: Some(level),
+            group,
+            last_key,
+        })
+    }
+}
+
+/// A batch of pairs returned by a scan.
+#[derive(Debug)]
+pub struct ScanBatch {
+    pub kv_pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The cursor to resume from. `None` at the end of the scan.
+    pub next: Option<ScanCursor>,
+}
+
+// How many levels the table may shrink during a scan.
+// A cursor at a higher level than that is rejected because it would read a bucket for each of too many groups.
+const MAX_SHRINK_LEVELS: u8 = 8;
+
+pub struct Scan<'a> {
+    pub db: &'a LinHashCore,
+    pub cursor: ScanCursor,
+    pub limit: usize,
+}
+
+impl Scan<'_> {
+    /// Returns at most `limit` pairs.
+    pub fn exec(self) -> Result<ScanBatch> {
+        let mut cursor = self.cursor;
+        if self.limit == 0 {
+            return Ok(ScanBatch {
+                kv_pairs: Vec::new(),
+                next: Some(cursor),
+            });
+        }
+        let base_level = self.db.root.read().base_level;
+        let level = *cursor.level.get_or_insert(base_level);
+        if level > base_level.saturating_add(MAX_SHRINK_LEVELS) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("scan cursor at level {level} but the table is at level {base_level}"),
+            )
+            .into());
+        }
+
+        let mut kv_pairs = Vec::new();
+        while cursor.group < 1 << level {
+            let mut group = list::read_group(self.db, level, cursor.group)?;
+            group.sort_unstable();
+
+            let start = match &cursor.last_key {
+                Some(last_key) => group.partition_point(|(k, _)| k <= last_key),
+                None => 0,
+            };
+            let n = (self.limit - kv_pairs.len()).min(group.len() - start);
+            let end = start + n;
+            let rest = group.len() - end;
+            kv_pairs.extend(group.drain(start..end));
+
+            if rest > 0 {
+                cursor.last_key = kv_pairs.last().map(|(k, _)| k.clone());
+                return Ok(ScanBatch {
+                    kv_pairs,
+                    next: Some(cursor),
+                });
+            }
+
+            cursor.group += 1;
+            cursor.last_key = None;
+            if kv_pairs.len() == self.limit {
+                break;
+            }
+        }
+
+        let done = cursor.group == 1 << level;
+        Ok(ScanBatch {
+            kv_pairs,
+            next: if done { None } else { Some(cursor) },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_cursor_bytes() {
+        let cursors = [
+            ScanCursor::start(),
+            ScanCursor {
+                level: Some(3),
+                group: 5,
+                last_key: None,
+            },
+            ScanCursor {
+                level: Some(10),
+                group: 1000,
+                last_key: Some(vec![1, 2, 3]),
+            },
+        ];
+        for cursor in cursors {
+            assert_eq!(ScanCursor::from_bytes(&cursor.to_bytes()).unwrap(), cursor);
+        }
+        assert!(ScanCursor::from_bytes(&[1, 2, 3]).is_err());
+
+        // The level or the group is out of range.
+        let cursor = |level: u8, group: u64| {
+            let mut buf = vec![level];
+            buf.extend(group.to_le_bytes());
+            buf
+        };
+        assert!(ScanCursor::from_bytes(&cursor(63, (1 << 63) - 1)).is_ok());
+        for buf in [
+            cursor(64, 0),
+            cursor(255, 0),
+            cursor(63, 1 << 63),
+            cursor(3, 8),
+            cursor(0, 1),
+        ] {
+            let e = ScanCursor::from_bytes(&buf).unwrap_err();
+            assert!(matches!(e, Error::IO(e) if e.kind() == std::io::ErrorKind::InvalidInput));
+        }
+    }
+}
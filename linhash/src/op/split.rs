@@ -2,106 +2,224 @@ use super::*;
 
 pub struct Split<'a> {
     pub db: &'a LinHashCore,
-    pub chain_id: PageChainId,
-    #[allow(unused)]
-    pub root: RwLockReadGuard<'a, Root>,
-    #[allow(unused)]
-    pub lock: lock::SelectiveLockGuard<'a>,
+    pub root: RwLockUpgradableReadGuard<'a, Root>,
+    /// The consecutive buckets from the split pointer.
+    pub buckets: Vec<(PageChainId, lock::SelectiveLockGuard<'a>)>,
+    /// The number of threads to prepare the splits.
+    pub n_threads: usize,
+}
+
+// The pages of a split which are written to commit it.
+struct Prepared {
+    // The new page first.
+    primary_pages: Vec<(u64, Page)>,
+    new_overflow_ids: Vec<u64>,
+    old_overflow_ids: Vec<u64>,
 }
 
 impl Split<'_> {
-    /// Split the primary page at `next_split_id` into two primary pages.
+    /// Split the buckets into two primary pages each and advance the split pointer.
+    /// The pointer is advanced past the splits committed before an error.
     pub fn exec(self) -> Result<()> {
-        let kv_pairs = self.collect_rehash_kv_pairs()?;
-        let page_chains = self.insert_kv_pairs_into_pages(kv_pairs);
-
-        // Write from bigger primary page id (new one) to avoid losing pairs on crash.
-        for (_, page_chain) in page_chains.into_iter().rev() {
-            // Write from overflow pages.
-            for (page_id, page) in page_chain.into_iter().rev() {
-                match page_id {
-                    PageId::Primary(id) => {
-                        // Before commiting the primary page, ensure that overflow pages is persisted.
-                        // Since split is rare, performance impact by sync call is small.
-                        self.db.overflow_pages.flush()?;
-                        self.db.primary_pages.write_page(id, &page)?;
-                        // We don't need to sync the primary page because losing the primary page doesn't affect consistency.
-                    }
-                    PageId::Overflow(id) => {
-                        self.db.overflow_pages.write_page(id, &page)?;
-                    }
+        self.db.mark_dirty(&self.root)?;
+
+        let chain_ids: Vec<PageChainId> = self.buckets.iter().map(|x| x.0).collect();
+        let prepared = self.prepare_all(&chain_ids);
+
+        // Before commiting the primary pages, ensure that overflow pages is persisted.
+        // Since split is rare, performance impact by sync call is small.
+        // Nothing is committed if the first bucket fails.
+        if prepared.first().is_some_and(|x| x.is_ok()) {
+            self.db.overflow_pages.flush()?;
+        }
+
+        // Commit in order so that only the last split can be incomplete on crash.
+        let mut n_committed = 0;
+        let mut error = None;
+        for prepared in prepared {
+            let prepared = match prepared {
+                Ok(prepared) if error.is_none() => prepared,
+                Ok(prepared) => {
+                    // The new overflow pages are never referenced.
+                    self.db.release_overflow_pages(prepared.new_overflow_ids);
+                    continue;
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                    continue;
+                }
+            };
+
+            // Write from bigger primary page id (new one) to avoid losing pairs on crash.
+            for (id, page) in &prepared.primary_pages {
+                // We don't need to sync the primary page because losing the primary page doesn't affect consistency.
+                if let Err(e) = self.db.primary_pages.write_page(*id, page) {
+                    error.get_or_insert(e);
+                    break;
                 }
             }
+            if error.is_some() {
+                continue;
+            }
+
+            // The old overflow pages are no longer referenced.
+            self.db.release_overflow_pages(prepared.old_overflow_ids);
+            n_committed += 1;
         }
 
-        Ok(())
+        // The root is unchanged so the operations aren't blocked.
+        if n_committed == 0 {
+            return match error {
+                Some(e) => Err(e),
+                None => Ok(()),
+            };
+        }
+
+        // Advance the split pointer without letting others see the old root in between.
+        // Under the old root, an insert would put a key of the new bucket into the old one
+        // where it is lost once the pointer is advanced,
+        // and LIST, which reads the buckets the root counts, would miss the pairs moved to the new bucket.
+        // The bucket locks are released first because the operations waiting for them hold the root read lock.
+        drop(self.buckets);
+        let mut root = RwLockUpgradableReadGuard::upgrade(self.root);
+        let seq = self.db.root_seq.begin_write(&root);
+        for _ in 0..n_committed {
+            root.advance_split_pointer();
+        }
+        seq.publish(&root);
+        // The optimistic readers that straddle the growth fail to validate the root.
+        if self.db.scale_lock_stripes {
+            self.db.locks.grow(root.calc_n_pages(), &root);
+        }
+        drop(seq);
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
-    // Collect all the kv-pairs which is reachable from the primary page at `next_split_id`.
-    fn collect_rehash_kv_pairs(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
-        let split_id = self.chain_id.primary_page_id;
+    // The results are in the order of the buckets.
+    fn prepare_all(&self, chain_ids: &[PageChainId]) -> Vec<Result<Prepared>> {
+        let n_threads = self.n_threads.clamp(1, chain_ids.len().max(1));
+        if n_threads == 1 {
+            return chain_ids.iter().map(|&x| self.prepare(x)).collect();
+        }
 
-        let mut out: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let chunk_size = chain_ids.len().div_ceil(n_threads);
+        std::thread::scope(|s| {
+            let handles: Vec<_> = chain_ids
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || chunk.iter().map(|&x| self.prepare(x)).collect::<Vec<_>>())
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    /// Rehash the pairs into the two new page chains and write their overflow pages.
+    /// The old chain is read page by page and the new overflow pages are written as they fill
+    /// so the memory is bounded regardless of the length of the chain.
+    fn prepare(&self, chain_id: PageChainId) -> Result<Prepared> {
+        let split_id = chain_id.primary_page_id;
+        let cur_level = chain_id.locallevel;
+        let new_split_id = split_id + (1 << cur_level);
 
-        let mut cur_page = self.db.primary_pages.read_page(split_id)?.unwrap();
+        let mut old_chain = ChainWriter::new(self.db, cur_level + 1);
+        let mut new_chain = ChainWriter::new(self.db, cur_level + 1);
+        let mut old_overflow_ids = Vec::new();
+
+        let mut cur_page = self.db.primary_pages.read_page(split_id)?;
         loop {
             for (k, v) in cur_page.kv_pairs {
-                out.push((k, v));
+                let hash = self.db.calc_hash(&k);
+                let b = hash & ((1 << (cur_level + 1)) - 1);
+                if b == split_id {
+                    old_chain.push(k, v)?;
+                } else {
+                    new_chain.push(k, v)?;
+                }
             }
 
             match cur_page.overflow_id {
                 Some(id) => {
-                    cur_page = self.db.overflow_pages.read_page(id)?.unwrap();
-                }
-                None => {
-                    break;
+                    old_overflow_ids.push(id);
+                    cur_page = self.db.overflow_pages.read_page(id)?;
                 }
+                None => break,
             }
         }
 
-        Ok(out)
+        let (old_page, mut new_overflow_ids) = old_chain.finish()?;
+        let (new_page, overflow_ids) = new_chain.finish()?;
+        new_overflow_ids.extend(overflow_ids);
+
+        Ok(Prepared {
+            primary_pages: vec![(new_split_id, new_page), (split_id, old_page)],
+            new_overflow_ids,
+            old_overflow_ids,
+        })
     }
+}
 
-    fn insert_kv_pairs_into_pages(
-        &self,
-        kv_pairs: Vec<(Vec<u8>, Vec<u8>)>,
-    ) -> BTreeMap<u64, VecDeque<(PageId, Page)>> {
-        let split_id = self.chain_id.primary_page_id;
-        let cur_level = self.chain_id.locallevel;
+/// Build a page chain front to back.
+/// Only the primary page and the tail page are kept in memory:
+/// an overflow page is written as soon as the next one is allocated.
+/// The primary page is returned to commit the chain.
+struct ChainWriter<'a> {
+    db: &'a LinHashCore,
+    primary_page: Page,
+    tail: Option<(u64, Page)>,
+    overflow_ids: Vec<u64>,
+}
 
-        let mut page_chains = BTreeMap::new();
-        let new_split_id = split_id + (1 << cur_level);
-        page_chains.insert(split_id, VecDeque::new());
-        page_chains.insert(new_split_id, VecDeque::new());
-        for (&primary_page_id, page_chain) in &mut page_chains {
-            let mut page = Page::new();
-            page.locallevel = Some(cur_level + 1);
+impl<'a> ChainWriter<'a> {
+    fn new(db: &'a LinHashCore, locallevel: u8) -> Self {
+        let mut primary_page = Page::new();
+        primary_page.locallevel = Some(locallevel);
+        Self {
+            db,
+            primary_page,
+            tail: None,
+            overflow_ids: Vec::new(),
+        }
+    }
 
-            page_chain.push_back((PageId::Primary(primary_page_id), page));
+    fn push(&mut self, k: Vec<u8>, v: Vec<u8>) -> Result<()> {
+        let tail_page = match &mut self.tail {
+            Some((_, page)) => page,
+            None => &mut self.primary_page,
+        };
+        if tail_page.kv_pairs.len() < self.db.max_kv_per_page as usize {
+            tail_page.insert(k, v);
+            return Ok(());
         }
 
-        for (k, v) in kv_pairs {
-            let hash = self.db.calc_hash(&k);
-            let b = hash & ((1 << (cur_level + 1)) - 1);
-            let tail = page_chains.get_mut(&b).unwrap().back_mut().unwrap();
+        let new_overflow_id = self.db.alloc_overflow_id();
+        tail_page.overflow_id = Some(new_overflow_id);
+        self.overflow_ids.push(new_overflow_id);
 
-            if tail.1.kv_pairs.len() < self.db.max_kv_per_page as usize {
-                tail.1.insert(k, v);
-                continue;
-            } else {
-                let new_overflow_id = self.db.next_overflow_id.fetch_add(1, Ordering::SeqCst);
-                tail.1.overflow_id = Some(new_overflow_id);
+        if let Some((id, page)) = self.tail.take() {
+            self.db.overflow_pages.write_page(id, &page)?;
+        }
 
-                let mut new_page = Page::new();
-                new_page.insert(k, v);
+        let mut new_page = Page::new();
+        new_page.insert(k, v);
+        self.tail = Some((new_overflow_id, new_page));
 
-                page_chains
-                    .get_mut(&b)
-                    .unwrap()
-                    .push_back((PageId::Overflow(new_overflow_id), new_page));
-            }
-        }
+        Ok(())
+    }
 
-        page_chains
+    /// Write the tail page and return the primary page with the ids of the overflow pages written.
+    fn finish(mut self) -> Result<(Page, Vec<u64>)> {
+        if let Some((id, page)) = self.tail.take() {
+            self.db.overflow_pages.write_page(id, &page)?;
+        }
+        Ok((self.primary_page, self.overflow_ids))
     }
 }
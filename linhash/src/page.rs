@@ -41,6 +41,10 @@ impl PageRef {
             .map(|(k, v)| (k.as_slice(), v.as_slice()))
     }
 
+    pub fn n_kv_pairs(&self) -> usize {
+        self.archived().kv_pairs.len()
+    }
+
     pub fn overflow_id(&self) -> Option<u64> {
         self.archived().overflow_id.as_ref().map(|x| x.to_native())
     }
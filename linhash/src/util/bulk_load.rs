@@ -0,0 +1,156 @@
+use super::*;
+
+use std::io::{BufWriter, Write};
+
+/// Build a database in an empty directory from a stream of kv-pairs.
+///
+/// The pairs are partitioned by the lower bits of the hash into spill files first,
+/// counting them on the way, and the table is sized from the count.
+/// Then each page chain is written exactly once.
+/// The pages are built in a staging directory and moved into `dir` when complete
+/// so an interrupted load doesn't leave a partial table behind.
+pub struct BulkLoad<'a> {
+    pub dir: &'a Path,
+    pub config: &'a LinHashConfig,
+}
+
+// A spill file is loaded in memory at a time.
+// The number of spill files is chosen from the expected number of buckets
+// so that a spill file holds about this many buckets.
+const SPILL_BUCKETS: u64 = 1024;
+// Without the expected number, a spill file holds 1/MIN_SPILLS of the pairs.
+const MIN_SPILLS: u64 = 64;
+
+impl BulkLoad<'_> {
+    pub fn exec(self, kv_pairs: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> Result<()> {
+        let dir = self.dir;
+        let config = self.config;
+
+        let primary_path = dir.join("primary");
+        let exists = match std::fs::metadata(&primary_path) {
+            Ok(meta) => meta.len() > 0,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e.into()),
+        };
+        if exists || meta::Superblock::read(dir)?.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("database already exists in {}", dir.display()),
+            )
+            .into());
+        }
+
+        let staging = dir.join("bulk_load");
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        std::fs::create_dir_all(&staging)?;
+
+        let db = LinHashCore::new(&staging, config.ksize, config.vsize, config.pagesize)?;
+
+        let kv_pairs = kv_pairs.into_iter();
+        // The expected number only decides the number of spill files, not the size of the table.
+        let expected = config.initial_capacity.max(kv_pairs.size_hint().0 as u64);
+        let expected_buckets = LinHashConfig {
+            initial_capacity: expected,
+            ..config.clone()
+        }
+        .calc_initial_buckets(db.max_kv_per_page);
+        // A power of two so that the buckets at a level of at least log2(n_spills)
+        // take their pairs from a single spill file.
+        let n_spills = (expected_buckets / SPILL_BUCKETS)
+            .max(MIN_SPILLS)
+            .next_power_of_two();
+        let spill_path = |i: u64| staging.join(format!("spill-{i}"));
+
+        let mut spills = Vec::new();
+        for i in 0..n_spills {
+            let f = std::fs::File::create(spill_path(i))?;
+            spills.push(BufWriter::new(f));
+        }
+        let mut n_pairs = 0;
+        for (k, v) in kv_pairs {
+            if k.len() != config.ksize || v.len() != config.vsize {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("kv-pair of size ({}, {})", k.len(), v.len()),
+                )
+                .into());
+            }
+            let spill = &mut spills[(db.calc_hash(&k) & (n_spills - 1)) as usize];
+            spill.write_all(&k)?;
+            spill.write_all(&v)?;
+            n_pairs += 1;
+        }
+        for spill in spills {
+            spill.into_inner().map_err(|e| e.into_error())?;
+        }
+
+        // The duplicated keys are counted too so the table may be a little larger.
+        let n_buckets = LinHashConfig {
+            initial_capacity: config.initial_capacity.max(n_pairs),
+            ..config.clone()
+        }
+        .calc_initial_buckets(db.max_kv_per_page);
+        let root = util::calc_root(n_buckets);
+
+        // The buckets of a group take their pairs from the spill files of the group.
+        // A group has a single spill file unless the table is smaller than the spill files.
+        let n_groups = n_spills.min(1 << root.base_level);
+
+        let mut n_items = 0;
+        for group in 0..n_groups {
+            // A later pair overwrites the earlier one with the same key.
+            let page_ids: Vec<u64> = (group..n_buckets).step_by(n_groups as usize).collect();
+            let mut buckets: Vec<HashMap<Vec<u8>, Vec<u8>>> =
+                page_ids.iter().map(|_| HashMap::new()).collect();
+            for i in (group..n_spills).step_by(n_groups as usize) {
+                let data = std::fs::read(spill_path(i))?;
+                std::fs::remove_file(spill_path(i))?;
+
+                for kv in data.chunks_exact(config.ksize + config.vsize) {
+                    let (k, v) = kv.split_at(config.ksize);
+                    let b = root.calc_page_chain_id(db.calc_hash(k)).primary_page_id;
+                    buckets[(b / n_groups) as usize].insert(k.to_vec(), v.to_vec());
+                }
+            }
+
+            for (page_id, bucket) in page_ids.into_iter().zip(buckets) {
+                n_items += bucket.len() as u64;
+
+                let locallevel = root.calc_page_chain_id(page_id).locallevel;
+                for (page_id, page) in db.build_chain(page_id, locallevel, bucket) {
+                    match page_id {
+                        PageId::Primary(id) => db.primary_pages.write_page(id, &page)?,
+                        PageId::Overflow(id) => db.overflow_pages.write_page(id, &page)?,
+                    }
+                }
+            }
+        }
+
+        db.n_items.store(n_items, Ordering::SeqCst);
+        db.overflow_pages.flush()?;
+        db.primary_pages.flush()?;
+        db.write_checkpoint(&root, true)?;
+        let max_kv_per_page = db.max_kv_per_page;
+        drop(db);
+
+        // The superblock is written last to complete the database.
+        for name in ["overflow", "primary", "checkpoint"] {
+            std::fs::rename(staging.join(name), dir.join(name))?;
+        }
+        std::fs::File::open(dir)?.sync_all()?;
+        meta::Superblock {
+            ksize: config.ksize as u64,
+            vsize: config.vsize as u64,
+            pagesize: config.pagesize as u64,
+            hash_mode: HashMode::current(),
+            min_buckets: config.calc_initial_buckets(max_kv_per_page),
+        }
+        .write(dir)?;
+
+        std::fs::remove_dir_all(&staging)?;
+
+        Ok(())
+    }
+}
@@ -2,16 +2,25 @@ use super::*;
 
 pub struct Init<'a> {
     pub db: &'a LinHashCore,
+    /// The number of primary pages to create. At least two.
+    pub n_buckets: u64,
 }
 
 impl Init<'_> {
     pub fn exec(self) -> Result<()> {
-        // Insert two empty pages if the primary pages are not initialized.
-        let mut init_page = Page::new();
-        init_page.locallevel = Some(1);
+        assert!(self.n_buckets >= 2);
 
-        self.db.primary_pages.write_page(0, &init_page)?;
-        self.db.primary_pages.write_page(1, &init_page)?;
+        // Drop the pages left by an interrupted creation.
+        self.db.primary_pages.truncate(0)?;
+
+        // Write the empty pages as if they were split from two pages.
+        let root = util::calc_root(self.n_buckets);
+        for page_id in 0..self.n_buckets {
+            let mut init_page = Page::new();
+            init_page.locallevel = Some(root.calc_page_chain_id(page_id).locallevel);
+            self.db.primary_pages.write_page(page_id, &init_page)?;
+        }
+        self.db.primary_pages.flush()?;
 
         Ok(())
     }
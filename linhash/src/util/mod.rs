@@ -1,13 +1,13 @@
 use super::*;
 
 mod restore;
-pub use restore::Restore;
+pub use restore::{Restore, calc_root};
+
+mod bulk_load;
+pub use bulk_load::BulkLoad;
 
 mod init;
 pub use init::Init;
 
-mod traverse_overflow_pages;
-pub use traverse_overflow_pages::TraverseOverflowPages;
-
 mod traverse_primary_pages;
 pub use traverse_primary_pages::TraversePrimaryPages;
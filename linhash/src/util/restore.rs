@@ -2,53 +2,227 @@ use super::*;
 
 pub struct Restore<'a> {
     pub db: &'a LinHashCore,
+    /// The number of threads to scan the table.
+    pub n_threads: usize,
 }
 
+// The number of buckets a scan thread takes at a time.
+const SCAN_BATCH: u64 = 64;
+
 impl Restore<'_> {
-    /// Returns the number of valid primary pages.
-    pub fn exec(self) -> Result<u64> {
-        let n_primary_pages = util::TraversePrimaryPages { db: self.db }.exec()?;
+    /// `n_buckets` of the report is zero if the table isn't initialized.
+    pub fn exec(self) -> Result<RecoveryReport> {
+        let t = std::time::Instant::now();
+
+        let layout = util::TraversePrimaryPages { db: self.db }.exec()?;
+        let n_primary_pages = layout.n_pages;
         if n_primary_pages < 2 {
-            return Ok(0);
+            return Ok(RecoveryReport::default());
         }
 
         let root = calc_root(n_primary_pages);
 
-        let next_overflow_id = {
-            let travere_range = util::TraverseOverflowPages { db: self.db, root }.exec()?;
-            travere_range.end
+        let scan = self.scan_buckets(&root, n_primary_pages)?;
+        let n_items = scan.n_items;
+        let damaged = scan.damaged;
+
+        let mut referenced = scan.overflow_ids;
+        referenced.sort_unstable();
+        referenced.dedup();
+        let overflow_range = match (referenced.first(), referenced.last()) {
+            (Some(&first), Some(&last)) => first..last + 1,
+            _ => 0..0,
         };
 
-        let n_items = self.traverse_all_pages(n_primary_pages)?;
+        let next_overflow_id = overflow_range.end.max(self.db.overflow_pages.n_pages()?);
+        let (free, orphaned) =
+            self.find_unreferenced_overflow_pages(&referenced, next_overflow_id)?;
+        let n_orphaned_overflow_pages = orphaned.len() as u64;
+
+        let mut free_list = free_list::FreeList::new(free);
+        // Overflow pages behind a corrupted page can't be found.
+        // Never reuse any page written not to overwrite them.
+        if damaged.is_empty() {
+            free_list.release(orphaned);
+        }
+        let n_free_overflow_pages = free_list.n_free();
 
         *self.db.root.write() = root;
         self.db
             .next_overflow_id
             .store(next_overflow_id, Ordering::SeqCst);
+        *self.db.free_list.lock() = free_list;
         self.db.n_items.store(n_items, Ordering::SeqCst);
+        *self.db.damaged.lock() = damaged.clone();
 
-        Ok(n_primary_pages)
+        Ok(RecoveryReport {
+            n_buckets: n_primary_pages,
+            base_level: root.base_level,
+            next_split_primary_page_id: root.next_split_primary_page_id,
+            discarded_split_page: layout.discarded_split_page,
+            overflow_range,
+            n_overflow_pages: referenced.len() as u64,
+            n_orphaned_overflow_pages,
+            n_free_overflow_pages,
+            n_items,
+            damaged_buckets: damaged.into_iter().collect(),
+            full_scan: true,
+            elapsed: t.elapsed(),
+            ..Default::default()
+        })
     }
 
-    /// Returns `n_items`
-    fn traverse_all_pages(&self, n_primary_pages: u64) -> Result<u64> {
-        let mut n_items = 0;
+    /// Restore the state from a clean checkpoint without scanning the table.
+    pub fn exec_checkpoint(self, checkpoint: &meta::Checkpoint) -> RecoveryReport {
+        let t = std::time::Instant::now();
+
+        let root = Root {
+            base_level: checkpoint.base_level,
+            next_split_primary_page_id: checkpoint.next_split_primary_page_id,
+        };
+
+        *self.db.root.write() = root;
+        self.db
+            .next_overflow_id
+            .store(checkpoint.next_overflow_id, Ordering::SeqCst);
+        *self.db.free_list.lock() =
+            free_list::FreeList::new(checkpoint.free_overflow_ids.iter().copied());
+        self.db.n_items.store(checkpoint.n_items, Ordering::SeqCst);
+        *self.db.damaged.lock() = checkpoint.damaged_buckets.iter().copied().collect();
+
+        RecoveryReport {
+            n_buckets: root.calc_n_pages(),
+            base_level: root.base_level,
+            next_split_primary_page_id: root.next_split_primary_page_id,
+            n_items: checkpoint.n_items,
+            n_free_overflow_pages: checkpoint.free_overflow_ids.len() as u64,
+            damaged_buckets: checkpoint.damaged_buckets.clone(),
+            elapsed: t.elapsed(),
+            ..Default::default()
+        }
+    }
 
-        for i in 0..n_primary_pages {
-            let mut cur_page = self.db.primary_pages.read_page(i)?.unwrap();
+    /// Split the overflow pages below `end` not referenced by any chain
+    /// into the free ones which are never written or already freed and the orphaned ones.
+    fn find_unreferenced_overflow_pages(
+        &self,
+        referenced: &[u64],
+        end: u64,
+    ) -> Result<(Vec<u64>, Vec<u64>)> {
+        let mut free = vec![];
+        let mut orphaned = vec![];
+        let mut referenced = referenced.iter().peekable();
+        for id in 0..end {
+            if referenced.next_if_eq(&&id).is_some() {
+                continue;
+            }
+            if self.db.overflow_pages.is_written(id)? {
+                orphaned.push(id);
+            } else {
+                free.push(id);
+            }
+        }
+        Ok((free, orphaned))
+    }
 
+    /// Scan all the buckets in parallel.
+    /// Each thread takes a batch of buckets at a time so that long chains don't make a straggler.
+    fn scan_buckets(&self, root: &Root, n_primary_pages: u64) -> Result<ScanSummary> {
+        let next_batch = AtomicU64::new(0);
+        let worker = || -> Result<ScanSummary> {
+            let mut out = ScanSummary::new();
             loop {
-                n_items += cur_page.kv_pairs.len() as u64;
+                let start = next_batch.fetch_add(SCAN_BATCH, Ordering::SeqCst);
+                if start >= n_primary_pages {
+                    return Ok(out);
+                }
+                let end = (start + SCAN_BATCH).min(n_primary_pages);
+                for page_id in start..end {
+                    self.scan_bucket(root, page_id, &mut out)?;
+                }
+            }
+        };
 
-                if let Some(overflow_id) = cur_page.overflow_id {
-                    cur_page = self.db.overflow_pages.read_page(overflow_id)?.unwrap();
-                } else {
-                    break;
+        let n_threads = self.n_threads.max(1);
+        if n_threads == 1 {
+            return worker();
+        }
+
+        std::thread::scope(|s| {
+            let handles: Vec<_> = (0..n_threads).map(|_| s.spawn(worker)).collect();
+
+            let mut out = ScanSummary::new();
+            for handle in handles {
+                out.merge(handle.join().unwrap()?);
+            }
+            Ok(out)
+        })
+    }
+
+    /// Walk the page chain of the bucket.
+    fn scan_bucket(&self, root: &Root, page_id: u64, out: &mut ScanSummary) -> Result<()> {
+        let mut bucket = ScanSummary::new();
+        match self.walk_chain(root, page_id, &mut bucket) {
+            Ok(()) => {}
+            // Quarantine the bucket and keep going.
+            // The overflow pages read so far are still counted as in use.
+            Err(Error::Corruption { .. }) => {
+                bucket.n_items = 0;
+                bucket.damaged.insert(page_id);
+            }
+            Err(e) => return Err(e),
+        }
+        out.merge(bucket);
+        Ok(())
+    }
+
+    fn walk_chain(&self, root: &Root, page_id: u64, out: &mut ScanSummary) -> Result<()> {
+        let mut cur_page = self.db.primary_pages.read_page_ref(page_id)?;
+
+        let expected = root.calc_page_chain_id(page_id).locallevel;
+        if cur_page.locallevel() != Some(expected) {
+            return Err(self
+                .db
+                .primary_pages
+                .corruption(page_id, CorruptionReason::LocalLevel));
+        }
+
+        loop {
+            out.n_items += cur_page.n_kv_pairs() as u64;
+
+            match cur_page.overflow_id() {
+                Some(id) => {
+                    out.overflow_ids.push(id);
+                    cur_page = self.db.overflow_pages.read_page_ref(id)?;
                 }
+                None => break,
             }
         }
 
-        Ok(n_items)
+        Ok(())
+    }
+}
+
+struct ScanSummary {
+    n_items: u64,
+    /// The overflow pages in use.
+    overflow_ids: Vec<u64>,
+    damaged: BTreeSet<u64>,
+}
+
+impl ScanSummary {
+    fn new() -> Self {
+        Self {
+            n_items: 0,
+            overflow_ids: Vec::new(),
+            damaged: BTreeSet::new(),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.n_items += other.n_items;
+        self.overflow_ids.extend(other.overflow_ids);
+        self.damaged.extend(other.damaged);
     }
 }
 
@@ -4,48 +4,65 @@ pub struct TraversePrimaryPages<'a> {
     pub db: &'a LinHashCore,
 }
 
+pub struct PrimaryLayout {
+    /// The number of committed primary pages.
+    pub n_pages: u64,
+    /// The new page of the last split if the split wasn't committed.
+    pub discarded_split_page: Option<u64>,
+}
+
 impl TraversePrimaryPages<'_> {
-    /// Returns the number of valid primary pages
-    pub fn exec(self) -> Result<u64> {
-        let n_simple = self.traverse_primary_pages_simple()?;
-        if n_simple < 2 {
-            return Ok(n_simple);
+    /// Only the last split is examined so unreadable pages in the middle
+    /// don't truncate the table.
+    pub fn exec(self) -> Result<PrimaryLayout> {
+        let n_written = match self.find_last_written_page()? {
+            Some(page_id) => page_id + 1,
+            None => 0,
+        };
+        if n_written <= 2 {
+            return Ok(PrimaryLayout {
+                n_pages: n_written,
+                discarded_split_page: None,
+            });
         }
 
-        let last_page_id = n_simple - 1;
-
-        let last_page = self.db.primary_pages.read_page_ref(last_page_id)?.unwrap();
-        let locallevel = last_page.locallevel().unwrap();
+        // The last page is the new page of the last split.
+        // A merge interrupted before dropping the last page looks like an uncommitted split.
+        let last_page_id = n_written - 1;
+        let locallevel = (64 - last_page_id.leading_zeros()) as u8;
         let old_page_id = calc_old_page_id(last_page_id, locallevel);
-        let old_page = self.db.primary_pages.read_page_ref(old_page_id)?.unwrap();
 
         // If both pages are committed, their locallevels must be the same.
-        let old_locallevel = old_page.locallevel().unwrap();
-        if old_locallevel == locallevel {
-            Ok(n_simple)
-        } else {
-            Ok(n_simple - 1)
+        // If the old page is unreadable, the split is considered committed to keep the new page.
+        match self.read_locallevel(old_page_id)? {
+            Some(old_locallevel) if old_locallevel != locallevel => Ok(PrimaryLayout {
+                n_pages: n_written - 1,
+                discarded_split_page: Some(last_page_id),
+            }),
+            _ => Ok(PrimaryLayout {
+                n_pages: n_written,
+                discarded_split_page: None,
+            }),
         }
     }
 
-    pub fn traverse_primary_pages_simple(&self) -> Result<u64> {
-        let page0 = self.db.primary_pages.read_page_ref(0)?;
-        if page0.is_none() {
-            return Ok(0);
-        }
-
-        let page1 = self.db.primary_pages.read_page_ref(1)?;
-        if page1.is_none() {
-            return Ok(1);
+    /// Find the last page which is written, whether it is readable or not.
+    fn find_last_written_page(&self) -> Result<Option<u64>> {
+        for page_id in (0..self.db.primary_pages.n_pages()?).rev() {
+            if self.db.primary_pages.is_written(page_id)? {
+                return Ok(Some(page_id));
+            }
         }
+        Ok(None)
+    }
 
-        for page_id in 2.. {
-            let Some(_) = self.db.primary_pages.read_page_ref(page_id)? else {
-                return Ok(page_id);
-            };
+    /// Returns `None` if the page is unreadable.
+    fn read_locallevel(&self, page_id: u64) -> Result<Option<u8>> {
+        match self.db.primary_pages.try_read_page_ref(page_id) {
+            Ok(page) => Ok(page.and_then(|page| page.locallevel())),
+            Err(Error::Corruption { .. }) => Ok(None),
+            Err(e) => Err(e),
         }
-
-        unreachable!()
     }
 }
 
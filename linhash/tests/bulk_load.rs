@@ -0,0 +1,77 @@
+use linhash::*;
+
+fn vec(i: u64) -> Vec<u8> {
+    i.to_le_bytes().to_vec()
+}
+
+#[test]
+fn test_bulk_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+
+    let n = 100000;
+    // The duplicated keys take the later values.
+    let kv_pairs = (0..n)
+        .map(|i| (vec(i), vec(0)))
+        .chain((0..n).map(|i| (vec(i), vec(i))));
+    let db = LinHash::bulk_load(dir.path(), config.clone(), kv_pairs).unwrap();
+    assert!(!db.recovery_report().full_scan);
+    assert_eq!(db.len(), n);
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+
+    db.insert(vec(n), vec(n)).unwrap();
+    drop(db);
+
+    // Can't load into an existing database.
+    let e = LinHash::bulk_load(dir.path(), config.clone(), [(vec(0), vec(0))]);
+    assert!(e.is_err());
+
+    // The table is consistent.
+    std::fs::remove_file(dir.path().join("checkpoint")).unwrap();
+    let db = LinHash::open(dir.path(), config).unwrap();
+    let report = db.recovery_report();
+    assert!(report.full_scan);
+    assert!(report.damaged_buckets.is_empty());
+    assert_eq!(report.n_orphaned_overflow_pages, 0);
+    assert_eq!(db.len(), n + 1);
+    assert!(!dir.path().join("bulk_load").exists());
+}
+
+#[test]
+fn test_bulk_load_without_size_hint() {
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let n = 100000;
+
+    let dir = tempfile::tempdir().unwrap();
+    let db =
+        LinHash::bulk_load(dir.path(), config.clone(), (0..n).map(|i| (vec(i), vec(i)))).unwrap();
+    let hinted = db.recovery_report().clone();
+    let overflow_len = std::fs::metadata(dir.path().join("overflow"))
+        .unwrap()
+        .len();
+    drop(db);
+
+    // The lower bound of the size hint is 0.
+    let kv_pairs = (0..n).map(|i| (vec(i), vec(i))).filter(|_| true);
+    assert_eq!(kv_pairs.size_hint().0, 0);
+    let dir = tempfile::tempdir().unwrap();
+    let db = LinHash::bulk_load(dir.path(), config, kv_pairs).unwrap();
+    assert!(hinted.n_buckets > 2);
+    assert_eq!(db.recovery_report().n_buckets, hinted.n_buckets);
+    let overflow = std::fs::metadata(dir.path().join("overflow")).unwrap();
+    assert_eq!(overflow.len(), overflow_len);
+    assert_eq!(db.len(), n);
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
@@ -74,3 +74,152 @@ fn test_parallel_insert_delete_get() {
         hdl.join().unwrap();
     }
 }
+
+#[test]
+fn test_parallel_delete_get() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = Arc::new(LinHash::open(dir.path(), config).unwrap());
+
+    let n = 2000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    // The odd keys are deleted and inserted again while the even keys stay.
+    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let mut readers = vec![];
+    for _ in 0..4 {
+        readers.push(std::thread::spawn({
+            let db = db.clone();
+            let done = done.clone();
+            move || {
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    let i = rand::random::<u64>() % (n / 2) * 2;
+                    assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+                }
+            }
+        }));
+    }
+
+    let mut writers = vec![];
+    for t in 0..4 {
+        writers.push(std::thread::spawn({
+            let db = db.clone();
+            move || {
+                for _ in 0..3 {
+                    for i in (0..n).filter(|i| i % 8 == 2 * t + 1) {
+                        db.delete(&vec(i)).unwrap();
+                    }
+                    for i in (0..n).filter(|i| i % 8 == 2 * t + 1) {
+                        db.insert(vec(i), vec(i)).unwrap();
+                    }
+                }
+            }
+        }));
+    }
+
+    for hdl in writers {
+        hdl.join().unwrap();
+    }
+    done.store(true, std::sync::atomic::Ordering::SeqCst);
+    for hdl in readers {
+        hdl.join().unwrap();
+    }
+    assert_eq!(db.len(), n);
+}
+
+#[test]
+fn test_optimistic_get() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .gc_interval(Some(Duration::from_millis(10)))
+        .build();
+    let db = Arc::new(LinHash::open(dir.path(), config).unwrap());
+
+    let n = 1000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    // The table grows and shrinks and the pages are reclaimed under the readers.
+    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let mut readers = vec![];
+    for _ in 0..4 {
+        readers.push(std::thread::spawn({
+            let db = db.clone();
+            let done = done.clone();
+            move || {
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    let i = rand::random::<u64>() % n;
+                    assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+                    assert_eq!(db.get(&vec(u64::MAX - i)).unwrap(), None);
+                }
+            }
+        }));
+    }
+
+    for i in n..4 * n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    for i in n..4 * n {
+        db.delete(&vec(i)).unwrap();
+    }
+    db.gc().unwrap();
+
+    done.store(true, std::sync::atomic::Ordering::SeqCst);
+    for hdl in readers {
+        hdl.join().unwrap();
+    }
+    assert_eq!(db.len(), n);
+}
+
+#[test]
+fn test_get_many_while_resizing() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .read_threads(4)
+        .build();
+    let db = Arc::new(LinHash::open(dir.path(), config).unwrap());
+
+    let n = 1000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let reader = std::thread::spawn({
+        let db = db.clone();
+        let done = done.clone();
+        move || {
+            let keys: Vec<Vec<u8>> = (0..n).map(vec).collect();
+            let key_refs: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+            while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                let vals = db.get_many(&key_refs).unwrap();
+                for (i, v) in vals.into_iter().enumerate() {
+                    assert_eq!(v, Some(vec(i as u64)));
+                }
+            }
+        }
+    });
+
+    for i in n..4 * n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    for i in n..4 * n {
+        db.delete(&vec(i)).unwrap();
+    }
+
+    done.store(true, std::sync::atomic::Ordering::SeqCst);
+    reader.join().unwrap();
+}
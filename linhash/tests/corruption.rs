@@ -0,0 +1,185 @@
+use linhash::*;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+fn vec(i: u64) -> Vec<u8> {
+    i.to_le_bytes().to_vec()
+}
+
+// Flip a byte in the data area of the page.
+fn corrupt_page(path: &Path, pagesize: u64, page_id: u64) {
+    let f = std::fs::OpenOptions::new()
+        .write(true)
+        .read(true)
+        .open(path)
+        .unwrap();
+    let offset = page_id * pagesize + 40;
+    let mut buf = [0; 1];
+    f.read_exact_at(&mut buf, offset).unwrap();
+    buf[0] ^= 0xff;
+    f.write_all_at(&buf, offset).unwrap();
+    f.sync_all().unwrap();
+}
+
+#[test]
+fn test_corrupted_primary_page() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(8192)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+
+    let n = 100;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    db.flush().unwrap();
+
+    corrupt_page(&dir.path().join("primary"), 8192, 0);
+
+    let mut n_ok = 0;
+    let mut corrupted = vec![];
+    for i in 0..n {
+        match db.get(&vec(i)) {
+            Ok(v) => {
+                assert_eq!(v, Some(vec(i)));
+                n_ok += 1;
+            }
+            Err(Error::Corruption {
+                page_id: 0, reason, ..
+            }) => {
+                assert_eq!(reason, CorruptionReason::ChecksumMismatch);
+                corrupted.push(i);
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+    assert!(n_ok > 0);
+    assert!(!corrupted.is_empty());
+
+    let i = corrupted[0];
+    assert!(matches!(
+        db.insert(vec(i), vec(i)),
+        Err(Error::Corruption { .. })
+    ));
+    assert!(matches!(db.delete(&vec(i)), Err(Error::Corruption { .. })));
+    assert!(db.list().any(|kv| kv.is_err()));
+    assert!(matches!(
+        db.scan_from(ScanCursor::start(), usize::MAX),
+        Err(Error::Corruption { .. })
+    ));
+}
+
+#[test]
+fn test_restore_with_corrupted_primary_page() {
+    let pagesize = 4096;
+    let n = 10000;
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(pagesize)
+        .build();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+
+    let primary = dir.path().join("primary");
+    let n_primary_pages = std::fs::metadata(&primary).unwrap().len() / pagesize as u64;
+    assert!(n_primary_pages > 8);
+
+    // A bucket in the middle and the last bucket.
+    for damaged in [3, n_primary_pages - 1] {
+        corrupt_page(&primary, pagesize as u64, damaged);
+        // Force the full scan.
+        std::fs::remove_file(dir.path().join("checkpoint")).unwrap();
+
+        let db = LinHash::open(dir.path(), config.clone()).unwrap();
+        assert_eq!(db.damaged_buckets(), vec![damaged]);
+
+        let mut n_lost = 0;
+        for i in 0..n {
+            match db.get(&vec(i)) {
+                Ok(v) => assert_eq!(v, Some(vec(i))),
+                Err(Error::Corruption { page_id, .. }) => {
+                    assert_eq!(page_id, damaged);
+                    n_lost += 1;
+                }
+                Err(e) => panic!("unexpected error: {e}"),
+            }
+        }
+        assert!(n_lost > 0);
+        assert_eq!(db.len(), n - n_lost);
+        drop(db);
+
+        // Repair the page for the next round.
+        corrupt_page(&primary, pagesize as u64, damaged);
+    }
+}
+
+#[test]
+fn test_failed_split_counted() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+    db.insert(vec(0), vec(0)).unwrap();
+    db.flush().unwrap();
+
+    // The first bucket to split.
+    corrupt_page(&dir.path().join("primary"), 4096, 0);
+
+    let mut i = 1;
+    while db.stat().n_failed_splits == 0 {
+        // The inserts into the damaged bucket fail.
+        db.insert(vec(i), vec(i)).ok();
+        i += 1;
+        assert!(i < 100000);
+    }
+}
+
+#[test]
+fn test_split_stops_at_damaged_bucket() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    db.insert(vec(0), vec(0)).unwrap();
+    drop(db);
+
+    // The bucket at the split pointer is quarantined on recovery.
+    corrupt_page(&dir.path().join("primary"), 4096, 0);
+    std::fs::remove_file(dir.path().join("checkpoint")).unwrap();
+    let db = LinHash::open(dir.path(), config).unwrap();
+    assert_eq!(db.damaged_buckets(), vec![0]);
+
+    // The load factor goes over the limit but no split is attempted.
+    let n = 2000;
+    let mut n_inserted = 0;
+    for i in 1..n {
+        if db.insert(vec(i), vec(i)).is_ok() {
+            n_inserted += 1;
+        }
+    }
+    assert!(n_inserted > 0);
+    let stat = db.stat();
+    assert!(stat.load_factor > 2.0);
+    assert_eq!(stat.n_failed_splits, 0);
+    assert_eq!(stat.n_assisted_splits, 0);
+    for i in 1..n {
+        if let Ok(v) = db.get(&vec(i)) {
+            assert_eq!(v, Some(vec(i)));
+        }
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::useless_vec)]
+
 #[test]
 fn example() {
     use linhash::{LinHash, LinHashConfig};
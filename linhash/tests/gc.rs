@@ -0,0 +1,82 @@
+use linhash::*;
+use std::os::unix::fs::MetadataExt;
+
+fn vec(i: u64) -> Vec<u8> {
+    i.to_le_bytes().to_vec()
+}
+
+#[test]
+fn test_reuse_freed_overflow_pages() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    let n = 50000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+
+    // Scan the table to see the overflow pages.
+    std::fs::remove_file(dir.path().join("checkpoint")).unwrap();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    let report = db.recovery_report().clone();
+    assert!(report.full_scan);
+    // All the pages freed by splits are punched.
+    assert_eq!(report.n_orphaned_overflow_pages, 0);
+    assert!(report.n_free_overflow_pages > 0);
+
+    let overflow = dir.path().join("overflow");
+    let meta = std::fs::metadata(&overflow).unwrap();
+    let n_pages = meta.len().div_ceil(4096);
+    let n_used = meta.blocks() * 512 / 4096;
+    // Allow some slack for the filesystem metadata.
+    assert!(n_used <= n_pages - report.n_free_overflow_pages + 16);
+
+    // New overflow pages come from the free pages first.
+    for i in n..n + 100 {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+    assert_eq!(std::fs::metadata(&overflow).unwrap().len(), meta.len());
+
+    // The free pages survive a clean shutdown.
+    let db = LinHash::open(dir.path(), config).unwrap();
+    assert!(!db.recovery_report().full_scan);
+    assert!(db.recovery_report().n_free_overflow_pages > 0);
+    for i in 0..n + 100 {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
+
+#[test]
+fn test_gc_without_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .gc_interval(None)
+        .build();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    let n = 50000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    // GC runs as the pages are freed by the splits.
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+    while db.stat().n_reclaimed_overflow_pages == 0 {
+        assert!(std::time::Instant::now() < deadline);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    drop(db);
+
+    std::fs::remove_file(dir.path().join("checkpoint")).unwrap();
+    let db = LinHash::open(dir.path(), config).unwrap();
+    assert_eq!(db.recovery_report().n_orphaned_overflow_pages, 0);
+    assert_eq!(db.len(), n);
+}
@@ -58,14 +58,13 @@ fn do_reference_test(pagesize: usize) {
                 assert_eq!(len1, len2);
             }
             Op::List => {
-                let mut list1: Vec<(Vec<u8>, Vec<u8>)> = db.list().collect();
+                let mut list1: Vec<(Vec<u8>, Vec<u8>)> =
+                    db.list().collect::<Result<_, _>>().unwrap();
                 list1.sort();
                 let mut list2: Vec<(Vec<u8>, Vec<u8>)> =
                     m.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
                 list2.sort();
 
-                // FIXME fails!!!
-
                 // For debugging.
                 // db.stat().show();
                 assert_eq!(list1.len(), list2.len());
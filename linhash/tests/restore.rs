@@ -1,3 +1,5 @@
+#![allow(clippy::unnecessary_cast)]
+
 use linhash::*;
 
 fn vec(i: u64) -> Vec<u8> {
@@ -40,3 +42,254 @@ fn do_test_restore(pagesize: usize) {
         assert_eq!(v, vec(i));
     }
 }
+
+#[test]
+fn test_reopen_config_mismatch() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(8192)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+    db.insert(vec(1), vec(1)).unwrap();
+    drop(db);
+
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let e = LinHash::open(dir.path(), config).err().unwrap();
+    assert!(matches!(
+        e,
+        Error::ConfigMismatch {
+            field: "pagesize",
+            expected: 4096,
+            found: 8192,
+        }
+    ));
+}
+
+#[test]
+fn test_open_existing() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(LinHash::open_existing(dir.path()).is_err());
+
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(8192)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+    for i in 0..1000 {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+
+    let config = LinHashConfig::load(dir.path()).unwrap();
+    assert_eq!(config.pagesize, 8192);
+
+    let db = LinHash::open_existing(dir.path()).unwrap();
+    assert_eq!(db.len(), 1000);
+    for i in 0..1000 {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
+
+// Copy the files of a closed database.
+fn copy_dir(from: &std::path::Path, to: &std::path::Path) {
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        std::fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+    }
+}
+
+// Emulate a crash: the pages as of the shutdown with the checkpoint as of now.
+// The database is dropped before copying so that no background thread writes meanwhile.
+// The checkpoint is written atomically so it can be read while the database is open.
+// A database created without a checkpoint crashes without one.
+fn crash_image(db: LinHash, dir: &std::path::Path) -> tempfile::TempDir {
+    let checkpoint = std::fs::read(dir.join("checkpoint")).ok();
+    drop(db);
+
+    let crashed = tempfile::tempdir().unwrap();
+    copy_dir(dir, crashed.path());
+    let path = crashed.path().join("checkpoint");
+    match checkpoint {
+        Some(checkpoint) => std::fs::write(path, checkpoint).unwrap(),
+        None => std::fs::remove_file(path).unwrap(),
+    }
+    crashed
+}
+
+#[test]
+fn test_recovery_report() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    assert!(db.recovery_report().created);
+    assert_eq!(db.recovery_report().n_buckets, 2);
+
+    let n = 10000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    db.flush().unwrap();
+    // Modify after the flush so that the checkpoint is stale.
+    db.delete(&vec(0)).unwrap();
+
+    let crashed = crash_image(db, dir.path());
+
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    let report = db.recovery_report().clone();
+    assert!(!report.created);
+    assert!(!report.full_scan);
+    assert!(report.clean_shutdown);
+    assert_eq!(report.n_items_at_shutdown, Some(n - 1));
+    assert_eq!(report.n_items, n - 1);
+    assert!(report.n_buckets > 2);
+    assert!(report.damaged_buckets.is_empty());
+    drop(db);
+
+    let db = LinHash::open(crashed.path(), config).unwrap();
+    let report = db.recovery_report();
+    assert!(!report.created);
+    assert!(report.full_scan);
+    assert!(!report.clean_shutdown);
+    assert_eq!(report.n_items_at_shutdown, None);
+    assert_eq!(report.n_items, n - 1);
+}
+
+#[test]
+fn test_open_from_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    let n = 10000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+
+    // Crash before any modification.
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    assert!(!db.recovery_report().full_scan);
+    let unmodified = crash_image(db, dir.path());
+
+    // Crash after a modification.
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    db.delete(&vec(0)).unwrap();
+    let modified = crash_image(db, dir.path());
+
+    // Crash after flushing the modification.
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    db.delete(&vec(1)).unwrap();
+    db.flush().unwrap();
+    let flushed = crash_image(db, dir.path());
+
+    let db = LinHash::open(unmodified.path(), config.clone()).unwrap();
+    assert!(!db.recovery_report().full_scan);
+    assert_eq!(db.len(), n);
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+    drop(db);
+
+    let db = LinHash::open(modified.path(), config.clone()).unwrap();
+    assert!(db.recovery_report().full_scan);
+    assert_eq!(db.len(), n - 1);
+    assert_eq!(db.get(&vec(0)).unwrap(), None);
+    drop(db);
+
+    let db = LinHash::open(flushed.path(), config).unwrap();
+    assert!(!db.recovery_report().full_scan);
+    assert_eq!(db.len(), n - 2);
+    assert_eq!(db.get(&vec(0)).unwrap(), None);
+    assert_eq!(db.get(&vec(1)).unwrap(), None);
+}
+
+#[test]
+fn test_clean_checkpoint_without_superblock() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    let n = 10000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+
+    // As if the creation crashed before writing the superblock.
+    std::fs::remove_file(dir.path().join("superblock")).unwrap();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    assert!(db.recovery_report().full_scan);
+    db.insert(vec(n), vec(n)).unwrap();
+    let crashed = crash_image(db, dir.path());
+
+    // The checkpoint ignored by the scan isn't trusted after the crash.
+    let db = LinHash::open(crashed.path(), config).unwrap();
+    assert!(db.recovery_report().full_scan);
+    assert_eq!(db.len(), n + 1);
+    for i in 0..n + 1 {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
+
+#[test]
+fn test_parallel_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    let n = 20000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    let crashed1 = crash_image(db, dir.path());
+    // The same crash image for both.
+    let crashed2 = tempfile::tempdir().unwrap();
+    copy_dir(crashed1.path(), crashed2.path());
+
+    let mut config = config;
+    config.recovery_threads = 1;
+    let db = LinHash::open(crashed1.path(), config.clone()).unwrap();
+    let sequential = db.recovery_report().clone();
+    drop(db);
+
+    config.recovery_threads = 8;
+    let db = LinHash::open(crashed2.path(), config).unwrap();
+    let parallel = db.recovery_report().clone();
+
+    assert!(sequential.full_scan);
+    assert!(parallel.full_scan);
+    assert_eq!(parallel.n_items, n);
+    assert_eq!(parallel.n_items, sequential.n_items);
+    assert_eq!(parallel.n_buckets, sequential.n_buckets);
+    assert_eq!(parallel.overflow_range, sequential.overflow_range);
+    assert_eq!(
+        parallel.n_orphaned_overflow_pages,
+        sequential.n_orphaned_overflow_pages
+    );
+    assert_eq!(parallel.damaged_buckets, sequential.damaged_buckets);
+
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
@@ -1,9 +1,29 @@
+#![allow(clippy::unnecessary_cast)]
+
 use linhash::*;
 
 fn vec(i: u64) -> Vec<u8> {
     i.to_le_bytes().to_vec()
 }
 
+// The config of the tests which look into the layout of the table.
+fn config() -> LinHashConfig {
+    LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build()
+}
+
+// Reopen the database scanning the whole table
+// so that the recovery report shows the layout on disk.
+fn reopen_with_scan(dir: &std::path::Path, config: LinHashConfig) -> LinHash {
+    std::fs::remove_file(dir.join("checkpoint")).unwrap();
+    let db = LinHash::open(dir, config).unwrap();
+    assert!(db.recovery_report().full_scan);
+    db
+}
+
 #[test]
 fn test_open() {
     let dir = tempfile::tempdir().unwrap();
@@ -78,8 +98,8 @@ fn test_list() {
     }
 
     let mut actual = vec![];
-    for (k, v) in db.list() {
-        actual.push((k, v));
+    for kv in db.list() {
+        actual.push(kv.unwrap());
     }
 
     expected.sort();
@@ -108,8 +128,8 @@ fn test_list_after_every_insert() {
         expected.push((vec(i), vec(i)));
 
         let mut actual = vec![];
-        for (k, v) in db.list() {
-            actual.push((k, v));
+        for kv in db.list() {
+            actual.push(kv.unwrap());
         }
 
         expected.sort();
@@ -146,8 +166,8 @@ fn test_list_after_delete() {
     }
 
     let mut actual = vec![];
-    for (k, v) in db.list() {
-        actual.push((k, v));
+    for kv in db.list() {
+        actual.push(kv.unwrap());
     }
 
     expected.sort();
@@ -213,6 +233,42 @@ fn test_delete() {
     }
 }
 
+#[test]
+fn test_delete_compacts_chains() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = LinHash::open(dir.path(), config()).unwrap();
+
+    let n = 20000;
+
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    // Leave one percent of the items.
+    for i in 0..n {
+        if i % 100 != 0 {
+            db.delete(&vec(i)).unwrap();
+        }
+    }
+    drop(db);
+
+    let db = reopen_with_scan(dir.path(), config());
+    let report = db.recovery_report();
+    assert_eq!(report.n_items, n / 100);
+    // Every bucket fits in the primary page.
+    assert_eq!(report.n_overflow_pages, 0);
+    assert_eq!(report.n_orphaned_overflow_pages, 0);
+
+    for i in 0..n {
+        let v = db.get(&vec(i)).unwrap();
+        if i % 100 == 0 {
+            assert_eq!(v, Some(vec(i)));
+        } else {
+            assert!(v.is_none());
+        }
+    }
+}
+
 #[test]
 fn test_insert_half_delete_get() {
     let dir = tempfile::tempdir().unwrap();
@@ -271,3 +327,584 @@ fn test_insert_half_delete_update() {
         assert_eq!(old, Some(vec(i)));
     }
 }
+
+#[test]
+fn test_merge() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = LinHash::open(dir.path(), config()).unwrap();
+
+    let n = 20000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+
+    let db = LinHash::open(dir.path(), config()).unwrap();
+    let n_buckets = db.recovery_report().n_buckets;
+    for i in 0..n {
+        if i % 100 != 0 {
+            db.delete(&vec(i)).unwrap();
+        }
+    }
+    drop(db);
+
+    let db = reopen_with_scan(dir.path(), config());
+    let report = db.recovery_report();
+    assert!(report.n_buckets < n_buckets / 10);
+    assert!(report.damaged_buckets.is_empty());
+    assert_eq!(db.len(), n / 100);
+
+    for i in 0..n {
+        let v = db.get(&vec(i)).unwrap();
+        if i % 100 == 0 {
+            assert_eq!(v, Some(vec(i)));
+        } else {
+            assert!(v.is_none());
+        }
+    }
+}
+
+#[test]
+fn test_split_policy() {
+    let n = 10000;
+    // Returns the number of buckets and overflow pages.
+    let layout = |split_policy| {
+        let dir = tempfile::tempdir().unwrap();
+        let config = LinHashConfig {
+            split_policy,
+            ..config()
+        };
+        let db = LinHash::open(dir.path(), config.clone()).unwrap();
+        for i in 0..n {
+            db.insert(vec(i), vec(i)).unwrap();
+        }
+        drop(db);
+
+        let db = reopen_with_scan(dir.path(), config);
+        for i in 0..n {
+            assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+        }
+        let report = db.recovery_report();
+        (report.n_buckets, report.n_overflow_pages)
+    };
+
+    let sparse = layout(SplitPolicy::LoadFactor(0.95));
+    assert!(layout(SplitPolicy::LoadFactor(0.5)).0 > sparse.0);
+    assert!(layout(SplitPolicy::OverflowAllocation).0 > 2);
+    let short = layout(SplitPolicy::ChainLength(1));
+    assert!(short.0 > sparse.0);
+    assert!(short.1 < sparse.1);
+}
+
+#[test]
+fn test_invalid_load_factors() {
+    let dir = tempfile::tempdir().unwrap();
+    let configs = [
+        LinHashConfig {
+            split_policy: SplitPolicy::LoadFactor(0.0),
+            merge_load_factor: 0.0,
+            ..config()
+        },
+        LinHashConfig {
+            split_policy: SplitPolicy::LoadFactor(-1.0),
+            merge_load_factor: 0.0,
+            ..config()
+        },
+        LinHashConfig {
+            split_policy: SplitPolicy::LoadFactor(f64::NAN),
+            ..config()
+        },
+        // Merges and splits would alternate.
+        LinHashConfig {
+            split_policy: SplitPolicy::LoadFactor(0.5),
+            merge_load_factor: 0.5,
+            ..config()
+        },
+        LinHashConfig {
+            merge_load_factor: -0.1,
+            ..config()
+        },
+    ];
+    for config in configs {
+        assert!(matches!(
+            LinHash::open(dir.path(), config),
+            Err(Error::IO(e)) if e.kind() == std::io::ErrorKind::InvalidInput
+        ));
+    }
+
+    let config = LinHashConfig {
+        split_policy: SplitPolicy::LoadFactor(0.5),
+        merge_load_factor: 0.0,
+        ..config()
+    };
+    LinHash::open(dir.path(), config).unwrap();
+}
+
+#[test]
+fn test_initial_capacity() {
+    let dir = tempfile::tempdir().unwrap();
+    let n = 20000;
+    let sized = LinHashConfig {
+        initial_capacity: n,
+        ..config()
+    };
+    let db = LinHash::open(dir.path(), sized).unwrap();
+    assert!(db.recovery_report().created);
+    let n_buckets = db.recovery_report().n_buckets;
+    assert!(n_buckets > 2);
+
+    // No split while loading the items.
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+
+    // The capacity is ignored on reopen.
+    let db = LinHash::open(dir.path(), config()).unwrap();
+    assert_eq!(db.recovery_report().n_buckets, n_buckets);
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+
+    // Never merged below the initial size.
+    for i in 0..n {
+        db.delete(&vec(i)).unwrap();
+    }
+    drop(db);
+
+    // The layout is recovered by a scan too.
+    let db = reopen_with_scan(dir.path(), config());
+    assert_eq!(db.recovery_report().n_buckets, n_buckets);
+    assert!(db.recovery_report().damaged_buckets.is_empty());
+    assert!(db.is_empty());
+}
+
+#[test]
+fn test_parallel_split() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig {
+        split_threads: 4,
+        ..config()
+    };
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+
+    let n = 50000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+    drop(db);
+
+    let db = reopen_with_scan(dir.path(), config);
+    let report = db.recovery_report();
+    assert!(report.damaged_buckets.is_empty());
+    assert_eq!(report.n_orphaned_overflow_pages, 0);
+    assert_eq!(db.len(), n);
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
+
+#[test]
+fn test_split_long_chains() {
+    let dir = tempfile::tempdir().unwrap();
+    let n = 3000;
+
+    // Never split to grow long chains.
+    let no_split = LinHashConfig {
+        split_policy: SplitPolicy::LoadFactor(f64::MAX),
+        max_load_factor: None,
+        ..config()
+    };
+    let db = LinHash::open(dir.path(), no_split).unwrap();
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    drop(db);
+
+    let db = LinHash::open(dir.path(), config()).unwrap();
+    assert_eq!(db.recovery_report().n_buckets, 2);
+    db.insert(vec(n), vec(n)).unwrap();
+    drop(db);
+
+    let db = reopen_with_scan(dir.path(), config());
+    let report = db.recovery_report();
+    assert!(report.n_buckets > 2);
+    assert!(report.damaged_buckets.is_empty());
+    assert_eq!(db.len(), n + 1);
+    for i in 0..n + 1 {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
+
+#[test]
+fn test_max_load_factor() {
+    let dir = tempfile::tempdir().unwrap();
+    let n = 20000;
+
+    // The split thread never splits so only the inserts do.
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .split_policy(SplitPolicy::LoadFactor(f64::MAX))
+        .max_load_factor(Some(1.0))
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+        assert!(db.stat().load_factor <= 1.0);
+    }
+
+    let stat = db.stat();
+    assert!(stat.n_assisted_splits > 0);
+    assert_eq!(stat.n_pending_splits, 0);
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
+
+#[test]
+fn test_split_batch_within_stripes() {
+    let dir = tempfile::tempdir().unwrap();
+    let n = 5000;
+
+    // Only the inserts split so a batch takes as many buckets as allowed.
+    let config = LinHashConfig {
+        lock_stripes: 4,
+        split_threads: 4,
+        split_policy: SplitPolicy::LoadFactor(f64::MAX),
+        max_load_factor: Some(1.0),
+        ..config()
+    };
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    assert!(db.stat().n_assisted_splits > 0);
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+    drop(db);
+
+    // A batch would lock a stripe twice.
+    let config = LinHashConfig {
+        lock_stripes: 1,
+        ..config
+    };
+    assert!(matches!(
+        LinHash::open(dir.path(), config),
+        Err(Error::IO(e)) if e.kind() == std::io::ErrorKind::InvalidInput
+    ));
+}
+
+#[test]
+fn test_scale_lock_stripes() {
+    let dir = tempfile::tempdir().unwrap();
+    let n = 20000;
+
+    let config = LinHashConfig {
+        lock_stripes: 4,
+        scale_lock_stripes: true,
+        ..config()
+    };
+    let db = LinHash::open(dir.path(), config.clone()).unwrap();
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    let lock_stat = db.stat().lock_stat;
+    assert!(lock_stat.n_stripes > 4);
+    assert!(lock_stat.n_acquired >= n);
+    drop(db);
+
+    let db = LinHash::open(dir.path(), config).unwrap();
+    let lock_stat = db.stat().lock_stat;
+    assert!(lock_stat.n_stripes >= db.recovery_report().n_buckets);
+    for i in 0..n {
+        assert_eq!(db.get(&vec(i)).unwrap(), Some(vec(i)));
+    }
+}
+
+#[test]
+fn test_list_while_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+
+    let n = 5000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    // The table splits and the upper half is deleted during the iteration.
+    let mut it = db.list();
+    let mut actual = vec![];
+    for kv in it.by_ref().take(100) {
+        actual.push(kv.unwrap());
+    }
+    for i in n..3 * n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+    for i in n / 2..n {
+        db.delete(&vec(i)).unwrap();
+    }
+    for kv in it {
+        actual.push(kv.unwrap());
+    }
+
+    actual.sort();
+    let len = actual.len();
+    actual.dedup();
+    assert_eq!(actual.len(), len);
+    for i in 0..n / 2 {
+        assert!(actual.binary_search(&(vec(i), vec(i))).is_ok());
+    }
+}
+
+#[test]
+fn test_scan_from() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+
+    let n = 5000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    // The cursor is persisted between the pages while the table grows.
+    let mut actual = vec![];
+    let mut cursor = ScanCursor::start().to_bytes();
+    let mut n_pages = 0;
+    loop {
+        let batch = db
+            .scan_from(ScanCursor::from_bytes(&cursor).unwrap(), 97)
+            .unwrap();
+        assert!(batch.kv_pairs.len() <= 97);
+        actual.extend(batch.kv_pairs);
+        n_pages += 1;
+
+        let Some(next) = batch.next else {
+            break;
+        };
+        cursor = next.to_bytes();
+
+        for i in 0..100 {
+            let i = n + n_pages * 100 + i;
+            db.insert(vec(i), vec(i)).unwrap();
+        }
+    }
+    assert!(n_pages > n / 97);
+
+    actual.sort();
+    let len = actual.len();
+    actual.dedup();
+    assert_eq!(actual.len(), len);
+    for i in 0..n {
+        assert!(actual.binary_search(&(vec(i), vec(i))).is_ok());
+    }
+
+    let batch = db.scan_from(ScanCursor::start(), 0).unwrap();
+    assert!(batch.kv_pairs.is_empty());
+    assert_eq!(batch.next, Some(ScanCursor::start()));
+
+    // A cursor far above the level of the table is rejected instead of reading each of its groups.
+    let mut cursor = vec![40];
+    cursor.extend(0u64.to_le_bytes());
+    let cursor = ScanCursor::from_bytes(&cursor).unwrap();
+    assert!(matches!(
+        db.scan_from(cursor, 10),
+        Err(Error::IO(e)) if e.kind() == std::io::ErrorKind::InvalidInput
+    ));
+}
+
+#[test]
+fn test_partitions() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+
+    let n = 5000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i)).unwrap();
+    }
+
+    // The table splits while the partitions are scanned.
+    let partitions = db.partitions(8);
+    assert_eq!(partitions.len(), 8);
+    let mut actual: Vec<(Vec<u8>, Vec<u8>)> = std::thread::scope(|s| {
+        s.spawn(|| {
+            for i in n..3 * n {
+                db.insert(vec(i), vec(i)).unwrap();
+            }
+        });
+        let handles: Vec<_> = partitions
+            .iter()
+            .map(|partition| s.spawn(|| partition.iter().collect::<Result<Vec<_>, _>>().unwrap()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    actual.sort();
+    let len = actual.len();
+    actual.dedup();
+    assert_eq!(actual.len(), len);
+    for i in 0..n {
+        assert!(actual.binary_search(&(vec(i), vec(i))).is_ok());
+    }
+
+    // More partitions than buckets.
+    let n_listed: usize = db
+        .partitions(1 << 12)
+        .iter()
+        .map(|p| p.iter().count())
+        .sum();
+    assert_eq!(n_listed as u64, 3 * n);
+
+    let partitions = db.partitions(usize::MAX);
+    assert_eq!(partitions.len() as u64, MAX_PARTITIONS);
+    let n_listed: usize = partitions.iter().map(|p| p.iter().count()).sum();
+    assert_eq!(n_listed as u64, 3 * n);
+}
+
+#[test]
+fn test_for_each() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+
+    let n = 5000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i + 1)).unwrap();
+    }
+
+    let mut n_visited = 0;
+    let mut sum = 0;
+    let res = db.for_each(|k, v| {
+        let k = u64::from_le_bytes(k.try_into().unwrap());
+        let v = u64::from_le_bytes(v.try_into().unwrap());
+        assert_eq!(v, k + 1);
+        n_visited += 1;
+        sum += k;
+        std::ops::ControlFlow::<()>::Continue(())
+    });
+    assert!(res.unwrap().is_continue());
+    assert_eq!(n_visited, n);
+    assert_eq!(sum, n * (n - 1) / 2);
+
+    let mut n_keys = 0;
+    let res = db.for_each_key(|_| {
+        n_keys += 1;
+        std::ops::ControlFlow::<()>::Continue(())
+    });
+    assert!(res.unwrap().is_continue());
+    assert_eq!(n_keys, n);
+
+    // Find a value and stop.
+    let found = db
+        .for_each_value(|v| {
+            if v == vec(100) {
+                std::ops::ControlFlow::Break(v.to_vec())
+            } else {
+                std::ops::ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+    assert_eq!(found, std::ops::ControlFlow::Break(vec(100)));
+}
+
+#[test]
+fn test_get_with() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LinHashConfig::builder()
+        .ksize(8)
+        .vsize(8)
+        .pagesize(4096)
+        .build();
+    let db = LinHash::open(dir.path(), config).unwrap();
+
+    let n = 1000;
+    for i in 0..n {
+        db.insert(vec(i), vec(i * 2)).unwrap();
+    }
+
+    for i in 0..n {
+        let v = db
+            .get_with(&vec(i), |v| {
+                v.map(|v| u64::from_le_bytes(v.try_into().unwrap()))
+            })
+            .unwrap();
+        assert_eq!(v, Some(i * 2));
+
+        let mut buf = [0; 8];
+        assert!(db.get_into(&vec(i), &mut buf).unwrap());
+        assert_eq!(u64::from_le_bytes(buf), i * 2);
+
+        assert!(db.contains_key(&vec(i)).unwrap());
+    }
+
+    assert!(db.get_with(&vec(n), |v| v.is_none()).unwrap());
+    let mut buf = [0; 8];
+    assert!(!db.get_into(&vec(n), &mut buf).unwrap());
+    assert!(!db.contains_key(&vec(n)).unwrap());
+    assert!(db.get_into(&vec(0), &mut [0; 4]).is_err());
+}
+
+#[test]
+fn test_get_many() {
+    // On the calling thread by default and on the spawned threads.
+    assert_eq!(config().read_threads, 1);
+    for read_threads in [1, 4] {
+        let dir = tempfile::tempdir().unwrap();
+        let db = LinHash::open(
+            dir.path(),
+            LinHashConfig {
+                read_threads,
+                ..config()
+            },
+        )
+        .unwrap();
+
+        let n = 5000;
+        for i in 0..n {
+            db.insert(vec(i), vec(i)).unwrap();
+        }
+
+        // Half of the keys are missing and some are duplicated.
+        let keys: Vec<Vec<u8>> = (0..2 * n).chain(0..10).map(vec).collect();
+        let key_refs: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+        let vals = db.get_many(&key_refs).unwrap();
+        assert_eq!(vals.len(), keys.len());
+        for (k, v) in keys.iter().zip(vals) {
+            let i = u64::from_le_bytes(k[..].try_into().unwrap());
+            if i < n {
+                assert_eq!(v, Some(vec(i)));
+            } else {
+                assert!(v.is_none());
+            }
+        }
+
+        assert!(db.get_many(&[]).unwrap().is_empty());
+    }
+}
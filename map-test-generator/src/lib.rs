@@ -32,6 +32,7 @@ struct OpChoiceGenerator {
     top_delete_miss: u32,
     top_delete_hit: u32,
     top_len: u32,
+    #[allow(dead_code)]
     top_list: u32,
 }
 
@@ -129,6 +130,7 @@ impl MapTestGenerator {
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Op {
         let choice = self.op_choice_generator.choose();
         let op = self.get_op(choice);
@@ -154,6 +156,7 @@ impl MapTestGenerator {
         random(self.vsize)
     }
 
+    #[allow(clippy::let_and_return)]
     fn gen_miss_k(&self) -> Vec<u8> {
         let k = loop {
             let k = self.k();
@@ -174,6 +177,7 @@ impl MapTestGenerator {
         Some(keys[idx].clone())
     }
 
+    #[allow(clippy::needless_return)]
     fn get_op(&self, choice: OpChoice) -> Op {
         use OpChoice::*;
 